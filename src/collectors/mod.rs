@@ -0,0 +1,104 @@
+pub use online_players::OnlinePlayers;
+pub use query::{Query, QueryMetrics};
+pub use raknet::RakNetPing;
+pub use rcon::Rcon;
+pub use rcon_rules::RconRules;
+pub use server_log::LogTailer;
+pub use server_status::ServerStatus;
+pub use sessions::Sessions;
+pub use slp::ServerListPing;
+pub use tps::{Tps, TpsFlavor};
+
+use crate::Result;
+
+mod online_players;
+mod query;
+mod raknet;
+mod rcon;
+mod rcon_rules;
+mod server_log;
+mod server_status;
+mod sessions;
+mod slp;
+mod tps;
+
+/// Splits `host[:port]` into host and port, ipv6 hosts have to be in brackets like `[::1]:25565`
+pub fn parse_address(address: &str, default_port: u16) -> Result<(String, u16)> {
+    let (host, port) = if address.starts_with('[') {
+        match address.find(']') {
+            Some(end) => (&address[1..end], &address[end + 1..]),
+            None => Err(format!("Missing closing bracket in address {}", address))?,
+        }
+    } else {
+        match address.rfind(':') {
+            Some(colon) => (&address[..colon], &address[colon..]),
+            None => (address, ""),
+        }
+    };
+
+    let port = match port {
+        "" => default_port,
+        port if port.starts_with(':') => port[1..]
+            .parse()
+            .map_err(|_| format!("Could not parse port of address {}", address))?,
+        _ => Err(format!("Could not parse address {}", address))?,
+    };
+
+    if host.is_empty() {
+        Err(format!("Missing host in address {}", address))?
+    }
+
+    Ok((String::from(host), port))
+}
+
+/// Removes `§` color and formatting codes from text sent by the server
+pub fn strip_formatting(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod parse_address {
+        use super::*;
+
+        #[test]
+        fn should_use_default_port() {
+            let actual = parse_address("mc.example.com", 25565).unwrap();
+
+            assert_eq!(actual, (String::from("mc.example.com"), 25565));
+        }
+
+        #[test]
+        fn should_parse_port() {
+            let actual = parse_address("localhost:25566", 25565).unwrap();
+
+            assert_eq!(actual, (String::from("localhost"), 25566));
+        }
+
+        #[test]
+        fn should_parse_ipv6() {
+            let actual = parse_address("[::1]:19132", 25565).unwrap();
+
+            assert_eq!(actual, (String::from("::1"), 19132));
+        }
+
+        #[test]
+        fn should_reject_invalid_port() {
+            assert!(parse_address("localhost:minecraft", 25565).is_err());
+            assert!(parse_address(":25565", 25565).is_err());
+        }
+    }
+}
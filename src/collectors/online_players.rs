@@ -0,0 +1,228 @@
+use crate::collectors::Sessions;
+use crate::prometheus_handler::StatCache;
+use crate::Result;
+use prometheus::{GaugeVec, Opts, Registry};
+use std::{collections::HashSet, sync::Mutex, time::SystemTime};
+
+#[derive(Default)]
+struct OnlineState {
+    players: HashSet<String>,
+    /// Whether joins or a player list were seen before
+    tracking: bool,
+}
+
+/// Tracks who is online from a live player list or joins and leaves in the log
+pub struct OnlinePlayers {
+    stat_cache: StatCache,
+    player_online: GaugeVec,
+    /// Times the sessions of players coming and going
+    sessions: Option<Sessions>,
+    state: Mutex<OnlineState>,
+}
+
+impl OnlinePlayers {
+    pub fn new(stat_cache: StatCache, sessions: Option<Sessions>) -> Result<Self> {
+        Ok(Self {
+            stat_cache,
+            player_online: GaugeVec::new(
+                Opts::new("mc_player_online", "whether the player is online"),
+                &["player"],
+            )?,
+            sessions,
+            state: Mutex::new(OnlineState::default()),
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.player_online.clone()))?;
+        if let Some(sessions) = &self.sessions {
+            sessions.register(registry)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn join(&self, player: &str, at: SystemTime) {
+        let player = self.identity(player);
+        let mut state = self.state.lock().unwrap();
+
+        self.start(&mut state, &player, Some(at));
+    }
+
+    pub fn leave(&self, player: &str, at: SystemTime) {
+        let player = self.identity(player);
+        let mut state = self.state.lock().unwrap();
+
+        self.end(&mut state, &player, at);
+    }
+
+    /// Ends the sessions of everyone online, e.g. once the server stopped
+    pub fn end_all(&self, at: SystemTime) {
+        let mut state = self.state.lock().unwrap();
+
+        let online: Vec<String> = state.players.iter().cloned().collect();
+        for player in online {
+            self.end(&mut state, &player, at);
+        }
+    }
+
+    /// Marks `players` as online and everyone else as offline,
+    /// players already online when tracking started get no session start
+    pub fn set_online(&self, players: &[String], at: SystemTime) {
+        let players: HashSet<String> = players.iter().map(|p| self.identity(p)).collect();
+        let mut state = self.state.lock().unwrap();
+
+        // Without anything tracked before, online players may have joined long ago
+        let start = if state.tracking { Some(at) } else { None };
+
+        let left: Vec<String> = state.players.difference(&players).cloned().collect();
+        for player in left {
+            self.end(&mut state, &player, at);
+        }
+
+        for player in players {
+            self.start(&mut state, &player, start);
+        }
+    }
+
+    fn start(&self, state: &mut OnlineState, player: &str, at: Option<SystemTime>) {
+        state.tracking = true;
+        if !state.players.insert(String::from(player)) {
+            return;
+        }
+
+        self.player_online.with_label_values(&[player]).set(1.0);
+        if let Some(sessions) = &self.sessions {
+            sessions.start(player, at, state.players.len());
+        }
+    }
+
+    fn end(&self, state: &mut OnlineState, player: &str, at: SystemTime) {
+        state.tracking = true;
+        self.player_online.with_label_values(&[player]).set(0.0);
+
+        if state.players.remove(player) {
+            if let Some(sessions) = &self.sessions {
+                sessions.end(player, at);
+            }
+        }
+    }
+
+    /// Name of the player as used in the `player` label of the stats, which may differ in case
+    fn identity(&self, name: &str) -> String {
+        let snapshot = self.stat_cache.snapshot();
+
+        snapshot
+            .players
+            .values()
+            .find(|player| player.name.eq_ignore_ascii_case(name))
+            .map(|player| player.name.clone())
+            .unwrap_or_else(|| String::from(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_player;
+    use crate::player::Player;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn mock_online() -> OnlinePlayers {
+        OnlinePlayers::new(StatCache::new().unwrap(), None).unwrap()
+    }
+
+    fn is_online(online: &OnlinePlayers, player: &str) -> f64 {
+        online.player_online.with_label_values(&[player]).get()
+    }
+
+    mod join {
+        use super::*;
+
+        #[test]
+        fn should_track_online_players() {
+            let online = mock_online();
+
+            online.join("Alice", at(100));
+            online.join("Bob", at(200));
+            online.leave("Alice", at(400));
+
+            assert_eq!(is_online(&online, "Alice"), 0.0);
+            assert_eq!(is_online(&online, "Bob"), 1.0);
+        }
+
+        #[test]
+        fn should_use_names_of_stats() {
+            let stat_cache = StatCache::new().unwrap();
+            stat_cache.update(vec![mock_player!(1)]);
+            let online = OnlinePlayers::new(stat_cache, None).unwrap();
+
+            online.join("NAME-1", at(100));
+
+            assert_eq!(is_online(&online, "name-1"), 1.0);
+        }
+    }
+
+    mod end_all {
+        use super::*;
+
+        #[test]
+        fn should_end_all_sessions() {
+            let online =
+                OnlinePlayers::new(StatCache::new().unwrap(), Some(Sessions::new().unwrap()))
+                    .unwrap();
+            online.join("Alice", at(100));
+            online.join("Bob", at(200));
+
+            online.end_all(at(500));
+
+            assert_eq!(is_online(&online, "Alice"), 0.0);
+            assert_eq!(is_online(&online, "Bob"), 0.0);
+            assert!(online.state.lock().unwrap().players.is_empty());
+        }
+    }
+
+    mod set_online {
+        use super::*;
+
+        #[test]
+        fn should_replace_online_players() {
+            let online = mock_online();
+            online.join("Alice", at(100));
+
+            online.set_online(&[String::from("Bob")], at(500));
+
+            assert_eq!(is_online(&online, "Alice"), 0.0);
+            assert_eq!(is_online(&online, "Bob"), 1.0);
+        }
+
+        #[test]
+        fn should_time_sessions() {
+            let online =
+                OnlinePlayers::new(StatCache::new().unwrap(), Some(Sessions::new().unwrap()))
+                    .unwrap();
+            let registry = Registry::new();
+            online.register(&registry).unwrap();
+
+            // Alice was online before tracking started, Bob joined later
+            online.set_online(&[String::from("Alice")], at(100));
+            online.set_online(&[String::from("Alice"), String::from("Bob")], at(200));
+            online.set_online(&[], at(500));
+
+            let families = registry.gather();
+            let duration = families
+                .iter()
+                .find(|family| family.get_name() == "mc_player_session_duration_seconds")
+                .unwrap();
+
+            assert_eq!(
+                duration.get_metric()[0].get_histogram().get_sample_sum(),
+                300.0
+            );
+        }
+    }
+}
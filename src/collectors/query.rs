@@ -0,0 +1,467 @@
+use crate::collectors::OnlinePlayers;
+use crate::Result;
+use prometheus::{Gauge, GaugeVec, Opts, Registry};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::{net::UdpSocket, time};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAGIC: [u8; 2] = [0xfe, 0xfd];
+const HANDSHAKE: u8 = 0x09;
+const STAT: u8 = 0x00;
+/// Session ids only use the lower 4 bits of every byte
+const SESSION_ID: i32 = 0x0d0e_0a0d;
+
+/// Everything a full stat request of the query protocol reports
+#[derive(Debug, Clone, PartialEq)]
+pub struct FullStat {
+    pub values: HashMap<String, String>,
+    pub players: Vec<String>,
+}
+
+impl FullStat {
+    fn value(&self, key: &str) -> &str {
+        self.values.get(key).map(|v| v.as_str()).unwrap_or_default()
+    }
+
+    /// Server software and plugins, e.g. `Paper on Bukkit 1.16.5: WorldEdit 7.2.0; Vault 1.7.3`
+    pub fn plugins(&self) -> (String, Vec<(String, String)>) {
+        let plugins = self.value("plugins");
+        let mut parts = plugins.splitn(2, ':');
+        let software = parts.next().unwrap_or_default().trim().to_string();
+
+        let plugins = parts
+            .next()
+            .unwrap_or_default()
+            .split(';')
+            .map(|plugin| plugin.trim())
+            .filter(|plugin| !plugin.is_empty())
+            .map(|plugin| match plugin.rfind(' ') {
+                Some(space) => (
+                    plugin[..space].trim().to_string(),
+                    plugin[space + 1..].to_string(),
+                ),
+                None => (String::from(plugin), String::new()),
+            })
+            .collect();
+
+        (software, plugins)
+    }
+}
+
+/// Client for the GameSpy4 based query protocol, enabled with `enable-query=true`
+pub struct Query {
+    host: String,
+    port: u16,
+}
+
+impl Query {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: String::from(host),
+            port,
+        }
+    }
+
+    pub async fn full_stat(&self) -> Result<FullStat> {
+        time::timeout(TIMEOUT, self.request()).await?
+    }
+
+    async fn request(&self) -> Result<FullStat> {
+        let local = if self.host.contains(':') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let mut socket = UdpSocket::bind(local).await?;
+        socket.connect((self.host.as_str(), self.port)).await?;
+
+        let mut buf = vec![0; 65535];
+
+        socket.send(&request(HANDSHAKE, &[])).await?;
+        let len = socket.recv(&mut buf).await?;
+        let token = parse_handshake(&buf[..len])?;
+
+        // Full stat requests are padded to 4 more bytes than basic ones
+        let mut payload = token.to_be_bytes().to_vec();
+        payload.extend_from_slice(&[0; 4]);
+        socket.send(&request(STAT, &payload)).await?;
+        let len = socket.recv(&mut buf).await?;
+
+        parse_full_stat(&buf[..len])
+    }
+}
+
+fn request(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut request = MAGIC.to_vec();
+    request.push(kind);
+    request.extend_from_slice(&SESSION_ID.to_be_bytes());
+    request.extend_from_slice(payload);
+
+    request
+}
+
+/// Strips type and session id of a response
+fn response_body(response: &[u8], kind: u8) -> Result<&[u8]> {
+    if response.len() < 5 || response[0] != kind {
+        Err("Server answered query with an unexpected packet")?
+    }
+    if response[1..5] != SESSION_ID.to_be_bytes() {
+        Err("Query response belongs to another session")?
+    }
+
+    Ok(&response[5..])
+}
+
+/// The challenge token is sent as null terminated decimal string
+fn parse_handshake(response: &[u8]) -> Result<i32> {
+    let body = response_body(response, HANDSHAKE)?;
+    let token = String::from_utf8_lossy(body);
+
+    Ok(token
+        .trim_end_matches('\0')
+        .parse()
+        .map_err(|_| "Could not parse query challenge token")?)
+}
+
+/// `splitnum\0\x80\0`, key value pairs up to an empty key, `\x01player_\0\0` and player names
+/// up to an empty name, all null terminated
+fn parse_full_stat(response: &[u8]) -> Result<FullStat> {
+    let body = response_body(response, STAT)?;
+    if body.len() < 11 {
+        Err("Query response is too short")?
+    }
+
+    let mut strings = body[11..]
+        .split(|b| *b == 0)
+        .map(|s| String::from_utf8_lossy(s).into_owned());
+
+    let mut values = HashMap::new();
+    loop {
+        match strings.next() {
+            Some(key) if !key.is_empty() => {
+                values.insert(key, strings.next().unwrap_or_default());
+            }
+            Some(_) => break,
+            None => Err("Query response ended within key values")?,
+        }
+    }
+
+    // Skip the `\x01player_\0` padding
+    let players = strings
+        .skip_while(|s| s.is_empty() || s.starts_with('\u{1}'))
+        .take_while(|s| !s.is_empty())
+        .collect();
+
+    Ok(FullStat { values, players })
+}
+
+/// Online players and server info reported by the query protocol
+pub struct QueryMetrics {
+    up: Gauge,
+    info: GaugeVec,
+    plugin_info: GaugeVec,
+    /// Label values currently set per metric, to remove outdated info
+    previous: Mutex<Previous>,
+    online_players: Arc<OnlinePlayers>,
+}
+
+#[derive(Default)]
+struct Previous {
+    info: Option<Vec<String>>,
+    plugins: HashSet<Vec<String>>,
+}
+
+impl QueryMetrics {
+    /// The player list is passed on to `online_players`
+    pub fn new(online_players: Arc<OnlinePlayers>) -> Result<Self> {
+        Ok(Self {
+            up: Gauge::new("mc_server_query_up", "whether the last query succeeded")?,
+            info: GaugeVec::new(
+                Opts::new("mc_server_query_info", "server version, map and software"),
+                &["version", "map", "game_type", "software"],
+            )?,
+            plugin_info: GaugeVec::new(
+                Opts::new("mc_server_plugin_info", "plugins loaded by the server"),
+                &["plugin", "version"],
+            )?,
+            previous: Mutex::new(Previous::default()),
+            online_players,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.up.clone()))?;
+        registry.register(Box::new(self.info.clone()))?;
+        registry.register(Box::new(self.plugin_info.clone()))?;
+
+        Ok(())
+    }
+
+    /// A failed query only marks the server as down, players keep their last state
+    pub fn update(&self, stat: Option<&FullStat>) {
+        let stat = match stat {
+            Some(stat) => stat,
+            None => {
+                self.up.set(0.0);
+                return;
+            }
+        };
+        self.up.set(1.0);
+
+        self.online_players
+            .set_online(&stat.players, SystemTime::now());
+
+        let mut previous = self.previous.lock().unwrap();
+
+        let (software, plugins) = stat.plugins();
+        let info = vec![
+            stat.value("version").to_string(),
+            stat.value("map").to_string(),
+            stat.value("gametype").to_string(),
+            software,
+        ];
+        if let Some(old) = previous.info.replace(info.clone()) {
+            if old != info {
+                let _ = self.info.remove_label_values(&as_strs(&old));
+            }
+        }
+        self.info.with_label_values(&as_strs(&info)).set(1.0);
+
+        let plugins: HashSet<Vec<String>> = plugins
+            .into_iter()
+            .map(|(plugin, version)| vec![plugin, version])
+            .collect();
+        for old in previous.plugins.difference(&plugins) {
+            let _ = self.plugin_info.remove_label_values(&as_strs(old));
+        }
+        for plugin in &plugins {
+            self.plugin_info
+                .with_label_values(&as_strs(plugin))
+                .set(1.0);
+        }
+        previous.plugins = plugins;
+    }
+}
+
+fn as_strs(values: &[String]) -> Vec<&str> {
+    values.iter().map(|v| v.as_str()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_player;
+    use crate::player::Player;
+    use crate::prometheus_handler::StatCache;
+    use std::net::SocketAddr;
+
+    fn mock_online_players() -> Arc<OnlinePlayers> {
+        Arc::new(OnlinePlayers::new(StatCache::new().unwrap(), None).unwrap())
+    }
+
+    fn player_online(registry: &Registry, player: &str) -> f64 {
+        registry
+            .gather()
+            .iter()
+            .find(|family| family.get_name() == "mc_player_online")
+            .and_then(|family| {
+                family
+                    .get_metric()
+                    .iter()
+                    .find(|metric| metric.get_label()[0].get_value() == player)
+                    .map(|metric| metric.get_gauge().get_value())
+            })
+            .unwrap()
+    }
+
+    fn mock_response(plugins: &str, players: &[&str]) -> Vec<u8> {
+        let mut response = vec![STAT];
+        response.extend_from_slice(&SESSION_ID.to_be_bytes());
+        response.extend_from_slice(b"splitnum\0\x80\0");
+        for (key, value) in [
+            ("hostname", "A Minecraft Server"),
+            ("gametype", "SMP"),
+            ("version", "1.16.5"),
+            ("plugins", plugins),
+            ("map", "world"),
+            ("numplayers", "2"),
+        ]
+        .iter()
+        {
+            response.extend_from_slice(key.as_bytes());
+            response.push(0);
+            response.extend_from_slice(value.as_bytes());
+            response.push(0);
+        }
+        response.extend_from_slice(b"\0\x01player_\0\0");
+        for player in players {
+            response.extend_from_slice(player.as_bytes());
+            response.push(0);
+        }
+        response.push(0);
+
+        response
+    }
+
+    /// Answers one handshake and one full stat request
+    async fn fake_server(response: Vec<u8>) -> SocketAddr {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0; 1500];
+
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], request(HANDSHAKE, &[]).as_slice());
+            let mut handshake = vec![HANDSHAKE];
+            handshake.extend_from_slice(&SESSION_ID.to_be_bytes());
+            handshake.extend_from_slice(b"9513307\0");
+            socket.send_to(&handshake, &peer).await.unwrap();
+
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[7..11], &9_513_307i32.to_be_bytes());
+            assert_eq!(len, 15);
+            socket.send_to(&response, &peer).await.unwrap();
+        });
+
+        addr
+    }
+
+    mod parse_full_stat {
+        use super::*;
+
+        #[test]
+        fn should_parse_values_and_players() {
+            let actual = parse_full_stat(&mock_response("", &["Alice", "Bob"])).unwrap();
+
+            assert_eq!(actual.value("version"), "1.16.5");
+            assert_eq!(actual.value("map"), "world");
+            assert_eq!(actual.players, vec!["Alice", "Bob"]);
+        }
+
+        #[test]
+        fn should_parse_empty_player_list() {
+            let actual = parse_full_stat(&mock_response("", &[])).unwrap();
+
+            assert!(actual.players.is_empty());
+        }
+
+        #[test]
+        fn should_reject_other_sessions() {
+            let mut response = mock_response("", &[]);
+            response[1] = 0x01;
+
+            assert!(parse_full_stat(&response).is_err());
+        }
+    }
+
+    mod plugins {
+        use super::*;
+
+        #[test]
+        fn should_split_software_and_plugins() {
+            let stat = parse_full_stat(&mock_response(
+                "Paper on Bukkit 1.16.5: WorldEdit 7.2.0; Vault",
+                &[],
+            ))
+            .unwrap();
+
+            let (software, plugins) = stat.plugins();
+
+            assert_eq!(software, "Paper on Bukkit 1.16.5");
+            assert_eq!(
+                plugins,
+                vec![
+                    (String::from("WorldEdit"), String::from("7.2.0")),
+                    (String::from("Vault"), String::new()),
+                ]
+            );
+        }
+
+        #[test]
+        fn should_be_empty_for_vanilla() {
+            let stat = parse_full_stat(&mock_response("", &[])).unwrap();
+
+            assert_eq!(stat.plugins(), (String::new(), vec![]));
+        }
+    }
+
+    mod full_stat {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_query_server() {
+            let addr = fake_server(mock_response("", &["Alice"])).await;
+            let query = Query::new("127.0.0.1", addr.port());
+
+            let actual = query.full_stat().await.unwrap();
+
+            assert_eq!(actual.players, vec!["Alice"]);
+        }
+    }
+
+    mod update {
+        use super::*;
+
+        #[test]
+        fn should_reset_players_that_left() {
+            let online_players = mock_online_players();
+            let metrics = QueryMetrics::new(online_players.clone()).unwrap();
+            let registry = Registry::new();
+            online_players.register(&registry).unwrap();
+
+            metrics.update(Some(
+                &parse_full_stat(&mock_response("", &["Alice", "Bob"])).unwrap(),
+            ));
+            metrics.update(Some(
+                &parse_full_stat(&mock_response("", &["Bob"])).unwrap(),
+            ));
+
+            assert_eq!(player_online(&registry, "Alice"), 0.0);
+            assert_eq!(player_online(&registry, "Bob"), 1.0);
+        }
+
+        #[test]
+        fn should_use_names_of_stats() {
+            let stat_cache = StatCache::new().unwrap();
+            stat_cache.update(vec![mock_player!(1)]);
+            let online_players = Arc::new(OnlinePlayers::new(stat_cache, None).unwrap());
+            let metrics = QueryMetrics::new(online_players.clone()).unwrap();
+            let registry = Registry::new();
+            online_players.register(&registry).unwrap();
+
+            metrics.update(Some(
+                &parse_full_stat(&mock_response("", &["NAME-1"])).unwrap(),
+            ));
+
+            assert_eq!(player_online(&registry, "name-1"), 1.0);
+        }
+
+        #[test]
+        fn should_remove_unloaded_plugins() {
+            let metrics = QueryMetrics::new(mock_online_players()).unwrap();
+            let registry = Registry::new();
+            metrics.register(&registry).unwrap();
+
+            metrics.update(Some(
+                &parse_full_stat(&mock_response("Paper: WorldEdit 7.2.0; Vault 1.7.3", &[]))
+                    .unwrap(),
+            ));
+            metrics.update(Some(
+                &parse_full_stat(&mock_response("Paper: WorldEdit 7.2.1", &[])).unwrap(),
+            ));
+
+            let families = registry.gather();
+            let plugins = families
+                .iter()
+                .find(|family| family.get_name() == "mc_server_plugin_info")
+                .unwrap();
+
+            assert_eq!(plugins.get_metric().len(), 1);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::collectors::{server_status::Status, strip_formatting};
+use crate::Result;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::{net::UdpSocket, time};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const UNCONNECTED_PING: u8 = 0x01;
+const UNCONNECTED_PONG: u8 = 0x1c;
+/// Marks offline messages of RakNet
+const MAGIC: [u8; 16] = [
+    0x00, 0xff, 0xff, 0x00, 0xfe, 0xfe, 0xfe, 0xfe, 0xfd, 0xfd, 0xfd, 0xfd, 0x12, 0x34, 0x56, 0x78,
+];
+const CLIENT_GUID: i64 = 0x6d63_6578_706f_7274;
+
+/// Asks a Bedrock edition server for its status like the friends tab does
+pub struct RakNetPing {
+    host: String,
+    port: u16,
+}
+
+impl RakNetPing {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: String::from(host),
+            port,
+        }
+    }
+
+    pub async fn ping(&self) -> Result<Status> {
+        time::timeout(TIMEOUT, self.request()).await?
+    }
+
+    async fn request(&self) -> Result<Status> {
+        let local = if self.host.contains(':') {
+            "[::]:0"
+        } else {
+            "0.0.0.0:0"
+        };
+        let mut socket = UdpSocket::bind(local).await?;
+        socket.connect((self.host.as_str(), self.port)).await?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let mut ping = vec![UNCONNECTED_PING];
+        ping.extend_from_slice(&timestamp.to_be_bytes());
+        ping.extend_from_slice(&MAGIC);
+        ping.extend_from_slice(&CLIENT_GUID.to_be_bytes());
+
+        let start = Instant::now();
+        socket.send(&ping).await?;
+
+        let mut buf = vec![0; 2048];
+        let len = socket.recv(&mut buf).await?;
+        let latency = start.elapsed();
+
+        let mut status = parse_pong(&buf[..len], timestamp)?;
+        status.latency = latency;
+
+        Ok(status)
+    }
+}
+
+/// Pong with time, server guid, magic and a string like
+/// `MCPE;Dedicated Server;422;1.16.201;2;10;13253860892328930865;Bedrock level;Survival;1;19132;19133;`
+fn parse_pong(pong: &[u8], timestamp: i64) -> Result<Status> {
+    if pong.len() < 35 || pong[0] != UNCONNECTED_PONG {
+        Err("Server answered ping with an unexpected packet")?
+    }
+    if pong[1..9] != timestamp.to_be_bytes() {
+        Err("Server answered another ping")?
+    }
+    if pong[17..33] != MAGIC {
+        Err("Pong is not a RakNet offline message")?
+    }
+
+    let length = u16::from_be_bytes([pong[33], pong[34]]) as usize;
+    let data = pong
+        .get(35..35 + length)
+        .ok_or("Pong is shorter than announced")?;
+    let data = String::from_utf8_lossy(data);
+    let fields: Vec<&str> = data.split(';').collect();
+
+    if fields.len() < 6 {
+        Err(format!("Could not parse pong {}", data))?
+    }
+    let number = |index: usize| {
+        fields[index]
+            .parse::<f64>()
+            .map_err(|_| format!("Could not parse pong {}", data))
+    };
+
+    Ok(Status {
+        online: number(4)?,
+        max: number(5)?,
+        protocol: number(2)?,
+        version: fields[3].to_string(),
+        motd: strip_formatting(fields[1]).trim().to_string(),
+        game_mode: fields
+            .get(8)
+            .filter(|mode| !mode.is_empty())
+            .map(|mode| mode.to_lowercase()),
+        latency: Duration::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    const SERVER_ID: &str = "MCPE;§aDedicated Server;422;1.16.201;2;10;13253860892328930865;Bedrock level;Survival;1;19132;19133;";
+
+    fn mock_pong(timestamp: &[u8], data: &str) -> Vec<u8> {
+        let mut pong = vec![UNCONNECTED_PONG];
+        pong.extend_from_slice(timestamp);
+        pong.extend_from_slice(&[0x42; 8]);
+        pong.extend_from_slice(&MAGIC);
+        pong.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        pong.extend_from_slice(data.as_bytes());
+
+        pong
+    }
+
+    /// Answers a single unconnected ping
+    async fn fake_server() -> SocketAddr {
+        let mut socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0; 1500];
+            let (len, peer) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 33);
+            assert_eq!(buf[0], UNCONNECTED_PING);
+            assert_eq!(&buf[9..25], &MAGIC);
+
+            socket
+                .send_to(&mock_pong(&buf[1..9], SERVER_ID), &peer)
+                .await
+                .unwrap();
+        });
+
+        addr
+    }
+
+    mod parse_pong {
+        use super::*;
+
+        #[test]
+        fn should_parse_server_id() {
+            let actual = parse_pong(&mock_pong(&7i64.to_be_bytes(), SERVER_ID), 7).unwrap();
+
+            assert_eq!(actual.online, 2.0);
+            assert_eq!(actual.max, 10.0);
+            assert_eq!(actual.protocol, 422.0);
+            assert_eq!(actual.version, "1.16.201");
+            assert_eq!(actual.motd, "Dedicated Server");
+            assert_eq!(actual.game_mode, Some(String::from("survival")));
+        }
+
+        #[test]
+        fn should_allow_missing_game_mode() {
+            let pong = mock_pong(&7i64.to_be_bytes(), "MCPE;Server;390;1.14.60;0;10");
+
+            let actual = parse_pong(&pong, 7).unwrap();
+
+            assert_eq!(actual.game_mode, None);
+        }
+
+        #[test]
+        fn should_reject_other_pings() {
+            assert!(parse_pong(&mock_pong(&8i64.to_be_bytes(), SERVER_ID), 7).is_err());
+        }
+
+        #[test]
+        fn should_reject_truncated_pongs() {
+            let mut pong = mock_pong(&7i64.to_be_bytes(), SERVER_ID);
+            pong.truncate(40);
+
+            assert!(parse_pong(&pong, 7).is_err());
+        }
+    }
+
+    mod ping {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_read_status() {
+            let addr = fake_server().await;
+            let raknet = RakNetPing::new("127.0.0.1", addr.port());
+
+            let actual = raknet.ping().await.unwrap();
+
+            assert_eq!(actual.online, 2.0);
+            assert_eq!(actual.version, "1.16.201");
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::collectors::strip_formatting;
+use crate::Result;
+use prometheus::{Gauge, Registry};
+use std::{error, io, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Mutex,
+    time,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Servers accept at most 1446 bytes of payload per command
+const MAX_COMMAND: usize = 1446;
+const MAX_PACKET: i32 = 1 << 16;
+
+const RESPONSE_VALUE: i32 = 0;
+const EXEC_COMMAND: i32 = 2;
+const AUTH: i32 = 3;
+
+struct Connection {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl Connection {
+    fn next_id(&mut self) -> i32 {
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+        self.next_id
+    }
+}
+
+/// Failed command, `unsent` if the server can not have run it
+struct Failure {
+    error: Box<dyn error::Error + Send + Sync>,
+    unsent: bool,
+}
+
+impl Failure {
+    fn sent(error: impl Into<Box<dyn error::Error + Send + Sync>>) -> Self {
+        Self {
+            error: error.into(),
+            unsent: false,
+        }
+    }
+
+    fn unsent(error: impl Into<Box<dyn error::Error + Send + Sync>>) -> Self {
+        Self {
+            error: error.into(),
+            unsent: true,
+        }
+    }
+}
+
+/// Runs commands over the Source RCON protocol, enabled with `enable-rcon=true`
+///
+/// The connection is kept open between commands and reopened once it broke. A command is only
+/// sent again if the kept connection broke before the server could have run it.
+pub struct Rcon {
+    addr: (String, u16),
+    password: String,
+    connection: Mutex<Option<Connection>>,
+    up: Gauge,
+}
+
+impl Rcon {
+    pub fn new(host: &str, port: u16, password: &str) -> Result<Self> {
+        Ok(Self {
+            addr: (String::from(host), port),
+            password: String::from(password),
+            connection: Mutex::new(None),
+            up: Gauge::new(
+                "mc_server_rcon_up",
+                "whether the last rcon command succeeded",
+            )?,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.up.clone()))?;
+
+        Ok(())
+    }
+
+    /// Returns the output of `command` without formatting codes
+    pub async fn command(&self, command: &str) -> Result<String> {
+        if command.len() > MAX_COMMAND {
+            Err(format!("RCON command is longer than {} bytes", MAX_COMMAND))?
+        }
+
+        let mut connection = self.connection.lock().await;
+
+        // A kept connection may have been closed by the server in the meantime, retry once
+        let reused = connection.is_some();
+        let result = match self.execute(&mut connection, command).await {
+            Err(failure) if reused && failure.unsent => {
+                self.execute(&mut connection, command).await
+            }
+            result => result,
+        };
+
+        self.up.set(if result.is_ok() { 1.0 } else { 0.0 });
+        result.map_err(|failure| failure.error)
+    }
+
+    async fn execute(
+        &self,
+        connection: &mut Option<Connection>,
+        command: &str,
+    ) -> std::result::Result<String, Failure> {
+        let mut current = match connection.take() {
+            Some(current) => current,
+            None => self.connect().await.map_err(Failure::unsent)?,
+        };
+
+        // On errors the connection is dropped, forcing a reconnect on the next command. After a
+        // timeout the server may still run the command, so it is not sent again.
+        let output = time::timeout(TIMEOUT, exchange(&mut current, command))
+            .await
+            .map_err(Failure::sent)??;
+        *connection = Some(current);
+
+        Ok(output)
+    }
+
+    async fn connect(&self) -> Result<Connection> {
+        let stream = time::timeout(
+            TIMEOUT,
+            TcpStream::connect((self.addr.0.as_str(), self.addr.1)),
+        )
+        .await??;
+        let mut connection = Connection { stream, next_id: 0 };
+
+        let id = connection.next_id();
+        time::timeout(
+            TIMEOUT,
+            authenticate(&mut connection.stream, id, &self.password),
+        )
+        .await??;
+
+        Ok(connection)
+    }
+}
+
+async fn authenticate(stream: &mut TcpStream, id: i32, password: &str) -> Result<()> {
+    write_packet(stream, id, AUTH, password).await?;
+
+    // Some servers send an empty response value before the auth response
+    loop {
+        let (response_id, kind, _) = read_packet(stream).await?;
+        if kind != EXEC_COMMAND {
+            continue;
+        }
+        if response_id == -1 {
+            Err("RCON authentication failed, check the password")?
+        }
+        if response_id == id {
+            return Ok(());
+        }
+    }
+}
+
+/// Long outputs are split over several packets, so an invalid request is sent right after the
+/// command and everything up to its answer belongs to the command
+async fn exchange(
+    connection: &mut Connection,
+    command: &str,
+) -> std::result::Result<String, Failure> {
+    let id = connection.next_id();
+    let end_id = connection.next_id();
+
+    write_packet(&mut connection.stream, id, EXEC_COMMAND, command)
+        .await
+        .map_err(Failure::unsent)?;
+    write_packet(&mut connection.stream, end_id, RESPONSE_VALUE, "")
+        .await
+        .map_err(Failure::sent)?;
+
+    let mut output = String::new();
+    let mut answered = false;
+    loop {
+        // A connection closed before any answer was most likely closed while idle
+        let (response_id, _, body) = match read_packet(&mut connection.stream).await {
+            Ok(packet) => packet,
+            Err(error) if !answered && is_closed(&*error) => Err(Failure::unsent(error))?,
+            Err(error) => Err(Failure::sent(error))?,
+        };
+        answered = true;
+
+        if response_id == end_id {
+            return Ok(strip_formatting(&output));
+        }
+        if response_id == id {
+            output.push_str(&body);
+        }
+    }
+}
+
+fn is_closed(error: &(dyn error::Error + 'static)) -> bool {
+    if let Some(error) = error.downcast_ref::<io::Error>() {
+        return error.kind() == io::ErrorKind::UnexpectedEof
+            || error.kind() == io::ErrorKind::ConnectionReset;
+    }
+
+    false
+}
+
+async fn write_packet(stream: &mut TcpStream, id: i32, kind: i32, body: &str) -> Result<()> {
+    let length = 4 + 4 + body.len() + 2;
+
+    let mut packet = Vec::with_capacity(4 + length);
+    packet.extend_from_slice(&(length as i32).to_le_bytes());
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.extend_from_slice(&kind.to_le_bytes());
+    packet.extend_from_slice(body.as_bytes());
+    packet.extend_from_slice(&[0, 0]);
+
+    stream.write_all(&packet).await?;
+
+    Ok(())
+}
+
+async fn read_packet(stream: &mut TcpStream) -> Result<(i32, i32, String)> {
+    let length = stream.read_i32_le().await?;
+    if !(10..=MAX_PACKET).contains(&length) {
+        Err(format!("Invalid RCON packet length {}", length))?
+    }
+
+    let mut packet = vec![0; length as usize];
+    stream.read_exact(&mut packet).await?;
+
+    let mut id = [0; 4];
+    id.copy_from_slice(&packet[0..4]);
+    let mut kind = [0; 4];
+    kind.copy_from_slice(&packet[4..8]);
+    let body = String::from_utf8_lossy(&packet[8..packet.len() - 2]).into_owned();
+
+    Ok((i32::from_le_bytes(id), i32::from_le_bytes(kind), body))
+}
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+    use tokio::net::TcpListener;
+
+    /// RCON server answering commands from `outputs`, splitting outputs into packets of at most
+    /// `split` bytes, returns its address and the number of accepted connections
+    pub async fn fake_rcon(
+        password: &'static str,
+        outputs: HashMap<&'static str, &'static str>,
+        split: usize,
+    ) -> (SocketAddr, Arc<AtomicUsize>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let outputs = Arc::new(outputs);
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let outputs = outputs.clone();
+
+                tokio::spawn(async move {
+                    while let Ok((id, kind, body)) = read_packet(&mut stream).await {
+                        match kind {
+                            AUTH if body == password => {
+                                write_packet(&mut stream, id, EXEC_COMMAND, "")
+                                    .await
+                                    .unwrap()
+                            }
+                            AUTH => write_packet(&mut stream, -1, EXEC_COMMAND, "")
+                                .await
+                                .unwrap(),
+                            EXEC_COMMAND if body == "close" => return,
+                            // Answers, then closes the connection like an idle timeout would
+                            EXEC_COMMAND if body == "quit" => {
+                                write_packet(&mut stream, id, RESPONSE_VALUE, "")
+                                    .await
+                                    .unwrap();
+                                return;
+                            }
+                            // Closes the connection in the middle of an answer
+                            EXEC_COMMAND if body == "cut" => {
+                                write_packet(&mut stream, id, RESPONSE_VALUE, "partial")
+                                    .await
+                                    .unwrap();
+                                return;
+                            }
+                            EXEC_COMMAND => {
+                                let output = outputs.get(body.as_str()).copied().unwrap_or("");
+                                let bytes = output.as_bytes();
+                                let mut chunks = bytes.chunks(split.max(1)).peekable();
+                                if chunks.peek().is_none() {
+                                    write_packet(&mut stream, id, RESPONSE_VALUE, "")
+                                        .await
+                                        .unwrap();
+                                }
+                                for chunk in chunks {
+                                    let chunk = String::from_utf8_lossy(chunk);
+                                    write_packet(&mut stream, id, RESPONSE_VALUE, &chunk)
+                                        .await
+                                        .unwrap();
+                                }
+                            }
+                            _ => write_packet(
+                                &mut stream,
+                                id,
+                                RESPONSE_VALUE,
+                                &format!("Unknown request {:x}", kind),
+                            )
+                            .await
+                            .unwrap(),
+                        }
+                    }
+                });
+            }
+        });
+
+        (addr, connections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::fake_rcon;
+    use super::*;
+    use std::{collections::HashMap, sync::atomic::Ordering};
+
+    mod command {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_return_output() {
+            let mut outputs = HashMap::new();
+            outputs.insert("list", "§6There are 1 of a max of 20 players online: Alice");
+            let (addr, _) = fake_rcon("secret", outputs, 4096).await;
+            let rcon = Rcon::new("127.0.0.1", addr.port(), "secret").unwrap();
+
+            let actual = rcon.command("list").await.unwrap();
+
+            assert_eq!(actual, "There are 1 of a max of 20 players online: Alice");
+            assert_eq!(rcon.up.get(), 1.0);
+        }
+
+        #[tokio::test]
+        async fn should_join_split_output() {
+            let mut outputs = HashMap::new();
+            outputs.insert("help", "a long list of commands");
+            let (addr, _) = fake_rcon("secret", outputs, 5).await;
+            let rcon = Rcon::new("127.0.0.1", addr.port(), "secret").unwrap();
+
+            let actual = rcon.command("help").await.unwrap();
+
+            assert_eq!(actual, "a long list of commands");
+        }
+
+        #[tokio::test]
+        async fn should_reuse_connection() {
+            let (addr, connections) = fake_rcon("secret", HashMap::new(), 4096).await;
+            let rcon = Rcon::new("127.0.0.1", addr.port(), "secret").unwrap();
+
+            rcon.command("list").await.unwrap();
+            rcon.command("list").await.unwrap();
+
+            assert_eq!(connections.load(Ordering::SeqCst), 1);
+        }
+
+        #[tokio::test]
+        async fn should_reconnect_after_close() {
+            let (addr, connections) = fake_rcon("secret", HashMap::new(), 4096).await;
+            let rcon = Rcon::new("127.0.0.1", addr.port(), "secret").unwrap();
+
+            rcon.command("list").await.unwrap();
+            let _ = rcon.command("close").await;
+            rcon.command("list").await.unwrap();
+
+            assert!(connections.load(Ordering::SeqCst) >= 2);
+        }
+
+        #[tokio::test]
+        async fn should_retry_on_closed_connection() {
+            let (addr, connections) = fake_rcon("secret", HashMap::new(), 4096).await;
+            let rcon = Rcon::new("127.0.0.1", addr.port(), "secret").unwrap();
+
+            let _ = rcon.command("quit").await;
+            rcon.command("list").await.unwrap();
+
+            assert_eq!(connections.load(Ordering::SeqCst), 2);
+        }
+
+        #[tokio::test]
+        async fn should_not_retry_after_answer() {
+            let (addr, connections) = fake_rcon("secret", HashMap::new(), 4096).await;
+            let rcon = Rcon::new("127.0.0.1", addr.port(), "secret").unwrap();
+            rcon.command("list").await.unwrap();
+
+            assert!(rcon.command("cut").await.is_err());
+            assert_eq!(connections.load(Ordering::SeqCst), 1);
+        }
+
+        #[tokio::test]
+        async fn should_fail_with_wrong_password() {
+            let (addr, _) = fake_rcon("secret", HashMap::new(), 4096).await;
+            let rcon = Rcon::new("127.0.0.1", addr.port(), "wrong").unwrap();
+
+            assert!(rcon.command("list").await.is_err());
+            assert_eq!(rcon.up.get(), 0.0);
+        }
+    }
+}
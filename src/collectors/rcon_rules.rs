@@ -0,0 +1,345 @@
+use crate::collectors::Rcon;
+use crate::Result;
+use prometheus::{CounterVec, GaugeVec, Opts, Registry};
+use regex::Regex;
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricType {
+    Gauge,
+    /// Increases of the extracted value are counted, decreases are treated as resets
+    Counter,
+}
+
+impl Default for MetricType {
+    fn default() -> Self {
+        MetricType::Gauge
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RuleConfig {
+    name: String,
+    #[serde(default)]
+    help: Option<String>,
+    command: String,
+    /// Seconds between two runs of the command
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// Named groups become labels, except `value` holding the value
+    regex: String,
+    #[serde(default, rename = "type")]
+    metric_type: MetricType,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+enum Metric {
+    Gauge(GaugeVec),
+    Counter(CounterVec),
+}
+
+/// Runs a command periodically and turns every match of its regex into a sample
+pub struct Rule {
+    pub command: String,
+    pub interval: Duration,
+    regex: Regex,
+    labels: Vec<String>,
+    metric: Metric,
+    /// Last value per label set, to remove gauges that disappeared and count increases
+    previous: Mutex<HashMap<Vec<String>, f64>>,
+}
+
+impl Rule {
+    fn from_config(config: RuleConfig) -> Result<Self> {
+        let regex = Regex::new(&config.regex)
+            .map_err(|e| format!("Invalid regex of rule {}: {}", config.name, e))?;
+
+        if !regex.capture_names().any(|name| name == Some("value")) {
+            Err(format!(
+                "Regex of rule {} has no named group `value`",
+                config.name
+            ))?
+        }
+        if config.interval == 0 {
+            Err(format!(
+                "Interval of rule {} has to be positive",
+                config.name
+            ))?
+        }
+
+        let labels: Vec<String> = regex
+            .capture_names()
+            .flatten()
+            .filter(|name| *name != "value")
+            .map(String::from)
+            .collect();
+        let label_names: Vec<&str> = labels.iter().map(|l| l.as_str()).collect();
+
+        let help = match config.help {
+            Some(help) => help,
+            None => format!("output of rcon command `{}`", config.command),
+        };
+        let opts = Opts::new(&config.name, &help);
+        let metric = match config.metric_type {
+            MetricType::Gauge => Metric::Gauge(GaugeVec::new(opts, &label_names)?),
+            MetricType::Counter => Metric::Counter(CounterVec::new(opts, &label_names)?),
+        };
+
+        Ok(Self {
+            command: config.command,
+            interval: Duration::from_secs(config.interval),
+            regex,
+            labels,
+            metric,
+            previous: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn register(&self, registry: &Registry) -> Result<()> {
+        match &self.metric {
+            Metric::Gauge(gauge) => registry.register(Box::new(gauge.clone()))?,
+            Metric::Counter(counter) => registry.register(Box::new(counter.clone()))?,
+        }
+
+        Ok(())
+    }
+
+    pub async fn collect(&self, rcon: &Rcon) -> Result<()> {
+        let output = rcon.command(&self.command).await?;
+        self.update(&output);
+
+        Ok(())
+    }
+
+    fn update(&self, output: &str) {
+        let samples = self.samples(output);
+        let mut previous = self.previous.lock().unwrap();
+
+        match &self.metric {
+            Metric::Gauge(gauge) => {
+                let current: HashSet<&Vec<String>> = samples.keys().collect();
+                for labels in previous.keys().filter(|labels| !current.contains(labels)) {
+                    let _ = gauge.remove_label_values(&as_strs(labels));
+                }
+                for (labels, value) in &samples {
+                    gauge.with_label_values(&as_strs(labels)).set(*value);
+                }
+                *previous = samples;
+            }
+            Metric::Counter(counter) => {
+                for (labels, value) in samples {
+                    let increase = match previous.get(&labels) {
+                        Some(old) if value >= *old => value - old,
+                        // First value or reset, counting starts over
+                        _ => value,
+                    };
+                    if increase > 0.0 {
+                        counter
+                            .with_label_values(&as_strs(&labels))
+                            .inc_by(increase);
+                    }
+                    previous.insert(labels, value);
+                }
+            }
+        }
+    }
+
+    /// Values of all matches, keyed by their label values in order of `labels`
+    fn samples(&self, output: &str) -> HashMap<Vec<String>, f64> {
+        let mut samples = HashMap::new();
+
+        for captures in self.regex.captures_iter(output) {
+            let value = match captures["value"].trim().parse::<f64>() {
+                Ok(value) => value,
+                Err(_) => {
+                    debug!("Could not parse value of `{}`", &captures[0]);
+                    continue;
+                }
+            };
+            let labels = self
+                .labels
+                .iter()
+                .map(|label| {
+                    captures
+                        .name(label)
+                        .map(|m| m.as_str().to_string())
+                        .unwrap_or_default()
+                })
+                .collect();
+
+            samples.insert(labels, value);
+        }
+
+        samples
+    }
+}
+
+fn as_strs(values: &[String]) -> Vec<&str> {
+    values.iter().map(|v| v.as_str()).collect()
+}
+
+/// Metrics extracted from the output of RCON commands, configured in a yaml file
+pub struct RconRules {
+    pub rules: Vec<Rule>,
+}
+
+impl RconRules {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+
+        RconRules::from(&content)
+    }
+
+    pub fn from(content: &str) -> Result<Self> {
+        let file: RulesFile = serde_yaml::from_str(content)?;
+        let rules = file
+            .rules
+            .into_iter()
+            .map(Rule::from_config)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { rules })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        for rule in &self.rules {
+            rule.register(registry)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collectors::rcon::fake::fake_rcon;
+
+    const VOTES: &str = "
+rules:
+  - name: mc_votes
+    help: votes per player
+    command: scoreboard players list @a
+    interval: 30
+    regex: '(?P<player>\\w+) has (?P<value>\\d+) \\[votes\\]'
+";
+
+    fn rule(metric_type: &str) -> Rule {
+        let content = format!("{}    type: {}\n", VOTES, metric_type);
+
+        RconRules::from(&content).unwrap().rules.remove(0)
+    }
+
+    mod from {
+        use super::*;
+
+        #[test]
+        fn should_parse_rules() {
+            let actual = &RconRules::from(VOTES).unwrap().rules[0];
+
+            assert_eq!(actual.command, "scoreboard players list @a");
+            assert_eq!(actual.interval, Duration::from_secs(30));
+            assert_eq!(actual.labels, vec!["player"]);
+        }
+
+        #[test]
+        fn should_require_value_group() {
+            let content = "
+rules:
+  - name: mc_votes
+    command: list
+    regex: '(?P<player>\\w+)'
+";
+
+            assert!(RconRules::from(content).is_err());
+        }
+
+        #[test]
+        fn should_reject_unknown_type() {
+            assert!(RconRules::from(&format!("{}    type: summary\n", VOTES)).is_err());
+        }
+    }
+
+    mod update {
+        use super::*;
+
+        #[test]
+        fn should_set_gauge_per_match() {
+            let rule = rule("gauge");
+
+            rule.update("Alice has 3 [votes]Bob has 5 [votes]");
+
+            match &rule.metric {
+                Metric::Gauge(gauge) => {
+                    assert_eq!(gauge.with_label_values(&["Alice"]).get(), 3.0);
+                    assert_eq!(gauge.with_label_values(&["Bob"]).get(), 5.0);
+                }
+                Metric::Counter(_) => unreachable!(),
+            }
+        }
+
+        #[test]
+        fn should_remove_missing_gauges() {
+            let rule = rule("gauge");
+            let registry = Registry::new();
+            rule.register(&registry).unwrap();
+
+            rule.update("Alice has 3 [votes]Bob has 5 [votes]");
+            rule.update("Bob has 6 [votes]");
+
+            assert_eq!(registry.gather()[0].get_metric().len(), 1);
+        }
+
+        #[test]
+        fn should_count_increases() {
+            let rule = rule("counter");
+
+            rule.update("Alice has 3 [votes]");
+            rule.update("Alice has 5 [votes]");
+            rule.update("Alice has 1 [votes]");
+
+            match &rule.metric {
+                Metric::Counter(counter) => {
+                    assert_eq!(counter.with_label_values(&["Alice"]).get(), 6.0)
+                }
+                Metric::Gauge(_) => unreachable!(),
+            }
+        }
+    }
+
+    mod collect {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_run_command() {
+            let mut outputs = HashMap::new();
+            outputs.insert("scoreboard players list @a", "§aAlice has 3 [votes]");
+            let (addr, _) = fake_rcon("secret", outputs, 4096).await;
+            let rcon = Rcon::new("127.0.0.1", addr.port(), "secret").unwrap();
+            let rule = rule("gauge");
+
+            rule.collect(&rcon).await.unwrap();
+
+            let previous = rule.previous.lock().unwrap();
+
+            assert_eq!(previous[&vec![String::from("Alice")]], 3.0);
+        }
+    }
+}
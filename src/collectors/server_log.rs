@@ -0,0 +1,647 @@
+use crate::collectors::OnlinePlayers;
+use crate::prometheus_handler::StatCache;
+use crate::Result;
+use prometheus::{Counter, IntCounter, IntCounterVec, Opts, Registry};
+use regex::Regex;
+use std::{
+    collections::HashSet,
+    fs::Metadata,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    fs::{self, File},
+    io::AsyncReadExt,
+    time,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref JOIN: Regex = Regex::new(r"^(?P<player>\w{1,16}) joined the game$").unwrap();
+    static ref LEAVE: Regex = Regex::new(r"^(?P<player>\w{1,16}) left the game$").unwrap();
+    static ref CHAT: Regex = Regex::new(r"^(\[Not Secure\] )?<(?P<player>\w{1,16})> ").unwrap();
+    static ref ADVANCEMENT: Regex = Regex::new(
+        r"^(?P<player>\w{1,16}) has (made the advancement|completed the challenge|reached the goal) \[(?P<advancement>.+)\]$"
+    )
+    .unwrap();
+    static ref LAG: Regex = Regex::new(
+        r"^Can't keep up! Is the server overloaded\? Running (?P<ms>\d+)ms or (?P<ticks>\d+) ticks behind"
+    )
+    .unwrap();
+    /// Beginnings of the vanilla death messages after the player name
+    static ref DEATH: Regex = Regex::new(
+        r"^(?P<player>\w{1,16}) (was |drowned|died|blew up|hit the ground too hard|fell |burned to death|went up in flames|went off with a bang|walked into|tried to swim in lava|suffocated|starved to death|withered away|experienced kinetic energy|froze to death|discovered the floor was lava|didn't want to live|left the confines of this world|suffered)"
+    )
+    .unwrap();
+}
+
+/// Something that happened on the server according to its log
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogEvent {
+    Join(String),
+    Leave(String),
+    Death(String),
+    Chat(String),
+    Advancement {
+        player: String,
+        advancement: String,
+    },
+    /// A `Can't keep up!` warning
+    Lag {
+        ticks: f64,
+    },
+    /// The server is shutting down, disconnecting everyone
+    Stop,
+    /// The server is starting, nobody can be online yet
+    Start,
+}
+
+impl LogEvent {
+    /// Parses a line like `[12:34:56] [Server thread/INFO]: Alice joined the game`,
+    /// death messages are only accepted for players in `players`
+    pub fn parse(line: &str, players: &HashSet<String>) -> Option<Self> {
+        let message = &line[line.find("]: ")? + 3..];
+        let message = message.trim_end();
+
+        if message == "Stopping server" {
+            return Some(LogEvent::Stop);
+        }
+        if message.starts_with("Starting minecraft server version") {
+            return Some(LogEvent::Start);
+        }
+        if let Some(captures) = JOIN.captures(message) {
+            return Some(LogEvent::Join(captures["player"].to_string()));
+        }
+        if let Some(captures) = LEAVE.captures(message) {
+            return Some(LogEvent::Leave(captures["player"].to_string()));
+        }
+        if let Some(captures) = CHAT.captures(message) {
+            return Some(LogEvent::Chat(captures["player"].to_string()));
+        }
+        if let Some(captures) = ADVANCEMENT.captures(message) {
+            return Some(LogEvent::Advancement {
+                player: captures["player"].to_string(),
+                advancement: captures["advancement"].to_string(),
+            });
+        }
+        if let Some(captures) = LAG.captures(message) {
+            return Some(LogEvent::Lag {
+                ticks: captures["ticks"].parse().ok()?,
+            });
+        }
+        if let Some(captures) = DEATH.captures(message) {
+            if players.contains(&captures["player"]) {
+                return Some(LogEvent::Death(captures["player"].to_string()));
+            }
+        }
+
+        None
+    }
+}
+
+/// Counters of the events found in the server log
+pub struct LogMetrics {
+    joins: IntCounterVec,
+    leaves: IntCounterVec,
+    deaths: IntCounterVec,
+    chat_messages: IntCounterVec,
+    advancements: IntCounterVec,
+    lag_warnings: IntCounter,
+    skipped_ticks: Counter,
+}
+
+impl LogMetrics {
+    pub fn new() -> Result<Self> {
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            IntCounterVec::new(Opts::new(name, help), labels)
+        };
+
+        Ok(Self {
+            joins: counter(
+                "mc_player_joins_total",
+                "times the player joined",
+                &["player"],
+            )?,
+            leaves: counter(
+                "mc_player_leaves_total",
+                "times the player left",
+                &["player"],
+            )?,
+            deaths: counter(
+                "mc_player_deaths_total",
+                "times the player died",
+                &["player"],
+            )?,
+            chat_messages: counter(
+                "mc_player_chat_messages_total",
+                "chat messages sent by the player",
+                &["player"],
+            )?,
+            advancements: counter(
+                "mc_player_advancements_total",
+                "advancements announced for the player",
+                &["player", "advancement"],
+            )?,
+            lag_warnings: IntCounter::new(
+                "mc_server_lag_warnings_total",
+                "times the server could not keep up",
+            )?,
+            skipped_ticks: Counter::new(
+                "mc_server_skipped_ticks_total",
+                "ticks the server skipped because it could not keep up",
+            )?,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.joins.clone()))?;
+        registry.register(Box::new(self.leaves.clone()))?;
+        registry.register(Box::new(self.deaths.clone()))?;
+        registry.register(Box::new(self.chat_messages.clone()))?;
+        registry.register(Box::new(self.advancements.clone()))?;
+        registry.register(Box::new(self.lag_warnings.clone()))?;
+        registry.register(Box::new(self.skipped_ticks.clone()))?;
+
+        Ok(())
+    }
+
+    pub fn record(&self, event: &LogEvent) {
+        match event {
+            LogEvent::Join(player) => self.joins.with_label_values(&[player]).inc(),
+            LogEvent::Leave(player) => self.leaves.with_label_values(&[player]).inc(),
+            LogEvent::Death(player) => self.deaths.with_label_values(&[player]).inc(),
+            LogEvent::Chat(player) => self.chat_messages.with_label_values(&[player]).inc(),
+            LogEvent::Advancement {
+                player,
+                advancement,
+            } => self
+                .advancements
+                .with_label_values(&[player, advancement])
+                .inc(),
+            LogEvent::Lag { ticks } => {
+                self.lag_warnings.inc();
+                self.skipped_ticks.inc_by(*ticks);
+            }
+            LogEvent::Stop | LogEvent::Start => {}
+        }
+    }
+}
+
+struct OpenLog {
+    file: File,
+    id: u64,
+    offset: u64,
+    /// Bytes after the last complete line
+    partial: Vec<u8>,
+}
+
+/// Follows `logs/latest.log` like `tail -F`, also after the server rotated it on startup or at
+/// midnight into `YYYY-MM-DD-n.log.gz`
+pub struct LogTailer {
+    path: PathBuf,
+    stat_cache: StatCache,
+    metrics: LogMetrics,
+    online_players: Arc<OnlinePlayers>,
+    log: Option<OpenLog>,
+    /// Players seen joining, next to the names known from their stats
+    joined: HashSet<String>,
+}
+
+impl LogTailer {
+    /// Joins and leaves are passed on to `online_players`
+    pub fn new(
+        path: &Path,
+        stat_cache: StatCache,
+        online_players: Arc<OnlinePlayers>,
+    ) -> Result<Self> {
+        Ok(Self {
+            path: PathBuf::from(path),
+            stat_cache,
+            metrics: LogMetrics::new()?,
+            online_players,
+            log: None,
+            joined: HashSet::new(),
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        self.metrics.register(registry)
+    }
+
+    /// Lines already in the log when the exporter starts are skipped
+    pub async fn run(mut self) {
+        if let Err(e) = self.open(true).await {
+            warn!("Could not open server log {}: {}", self.path.display(), e);
+        }
+
+        loop {
+            if let Err(e) = self.poll().await {
+                warn!("Could not read server log {}: {}", self.path.display(), e);
+                self.log = None;
+            }
+
+            time::delay_for(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Reads new lines and switches to the new file once the log was rotated
+    async fn poll(&mut self) -> Result<Vec<LogEvent>> {
+        if self.log.is_none() && !self.open(false).await? {
+            return Ok(vec![]);
+        }
+
+        let mut events = self.read().await?;
+
+        let rotated = match (fs::metadata(&self.path).await, &self.log) {
+            (Ok(metadata), Some(log)) => {
+                file_id(&metadata) != log.id || metadata.len() < log.offset
+            }
+            _ => false,
+        };
+        if rotated {
+            // Lines written right before the rotation are still read from the old file
+            events.extend(self.read().await?);
+            self.log = None;
+            if self.open(false).await? {
+                events.extend(self.read().await?);
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Returns whether the log exists
+    async fn open(&mut self, at_end: bool) -> Result<bool> {
+        let metadata = match fs::metadata(&self.path).await {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(false),
+        };
+
+        let mut file = File::open(&self.path).await?;
+        let offset = if at_end {
+            file.seek(SeekFrom::End(0)).await?
+        } else {
+            0
+        };
+
+        self.log = Some(OpenLog {
+            file,
+            id: file_id(&metadata),
+            offset,
+            partial: vec![],
+        });
+
+        Ok(true)
+    }
+
+    async fn read(&mut self) -> Result<Vec<LogEvent>> {
+        let log = match &mut self.log {
+            Some(log) => log,
+            None => return Ok(vec![]),
+        };
+
+        let mut buf = vec![];
+        let read = log.file.read_to_end(&mut buf).await?;
+        log.offset += read as u64;
+        log.partial.extend_from_slice(&buf);
+
+        let end = match log.partial.iter().rposition(|b| *b == b'\n') {
+            Some(end) => end,
+            None => return Ok(vec![]),
+        };
+        let lines: Vec<u8> = log.partial.drain(..=end).collect();
+
+        let mut players: HashSet<String> = self
+            .stat_cache
+            .snapshot()
+            .players
+            .values()
+            .map(|player| player.name.clone())
+            .collect();
+        players.extend(self.joined.iter().cloned());
+
+        let mut events = vec![];
+        for line in String::from_utf8_lossy(&lines).lines() {
+            if let Some(event) = LogEvent::parse(line, &players) {
+                match &event {
+                    LogEvent::Join(player) => {
+                        self.joined.insert(player.clone());
+                        players.insert(player.clone());
+                        self.online_players.join(player, SystemTime::now());
+                    }
+                    LogEvent::Leave(player) => self.online_players.leave(player, SystemTime::now()),
+                    // After a crash no leaves were logged, a starting server has nobody online
+                    LogEvent::Stop | LogEvent::Start => {
+                        self.online_players.end_all(SystemTime::now())
+                    }
+                    _ => {}
+                }
+
+                self.metrics.record(&event);
+                events.push(event);
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+/// Identifies the file behind the path, the inode on unix
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.ino()
+}
+
+/// Without inodes rotation is only noticed by the log getting shorter
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs as std_fs, io::Write};
+
+    fn players(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn mock_tailer(path: &Path) -> LogTailer {
+        let stat_cache = StatCache::new().unwrap();
+        let online_players = Arc::new(OnlinePlayers::new(stat_cache.clone(), None).unwrap());
+
+        LogTailer::new(path, stat_cache, online_players).unwrap()
+    }
+
+    fn player_online(registry: &Registry, player: &str) -> f64 {
+        registry
+            .gather()
+            .iter()
+            .find(|family| family.get_name() == "mc_player_online")
+            .and_then(|family| {
+                family
+                    .get_metric()
+                    .iter()
+                    .find(|metric| metric.get_label()[0].get_value() == player)
+                    .map(|metric| metric.get_gauge().get_value())
+            })
+            .unwrap()
+    }
+
+    fn log_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mc-exporter-{}-{}", name, std::process::id()));
+        let _ = std_fs::remove_dir_all(&dir);
+        std_fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn append(path: &Path, lines: &[&str]) {
+        let mut file = std_fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        for line in lines {
+            writeln!(file, "{}", line).unwrap();
+        }
+    }
+
+    mod parse {
+        use super::*;
+
+        #[test]
+        fn should_parse_joins_and_leaves() {
+            let none = players(&[]);
+
+            assert_eq!(
+                LogEvent::parse(
+                    "[12:00:00] [Server thread/INFO]: Alice joined the game",
+                    &none
+                ),
+                Some(LogEvent::Join(String::from("Alice")))
+            );
+            assert_eq!(
+                LogEvent::parse("[12:00:00 INFO]: Alice left the game", &none),
+                Some(LogEvent::Leave(String::from("Alice")))
+            );
+        }
+
+        #[test]
+        fn should_parse_chat() {
+            let actual = LogEvent::parse(
+                "[12:00:00] [Async Chat Thread - #0/INFO]: <Alice> Bob joined the game",
+                &players(&[]),
+            );
+
+            assert_eq!(actual, Some(LogEvent::Chat(String::from("Alice"))));
+        }
+
+        #[test]
+        fn should_parse_advancements() {
+            let actual = LogEvent::parse(
+                "[12:00:00] [Server thread/INFO]: Alice has made the advancement [Diamonds!]",
+                &players(&[]),
+            );
+
+            assert_eq!(
+                actual,
+                Some(LogEvent::Advancement {
+                    player: String::from("Alice"),
+                    advancement: String::from("Diamonds!"),
+                })
+            );
+        }
+
+        #[test]
+        fn should_parse_lag_warnings() {
+            let actual = LogEvent::parse(
+                "[12:00:00] [Server thread/WARN]: Can't keep up! Is the server overloaded? Running 2503ms or 50 ticks behind",
+                &players(&[]),
+            );
+
+            assert_eq!(actual, Some(LogEvent::Lag { ticks: 50.0 }));
+        }
+
+        #[test]
+        fn should_parse_stops() {
+            let actual = LogEvent::parse(
+                "[23:00:00] [Server thread/INFO]: Stopping server",
+                &players(&[]),
+            );
+
+            assert_eq!(actual, Some(LogEvent::Stop));
+        }
+
+        #[test]
+        fn should_parse_starts() {
+            let actual = LogEvent::parse(
+                "[08:00:00] [Server thread/INFO]: Starting minecraft server version 1.16.5",
+                &players(&[]),
+            );
+
+            assert_eq!(actual, Some(LogEvent::Start));
+        }
+
+        #[test]
+        fn should_only_accept_deaths_of_known_players() {
+            let line = "[12:00:00] [Server thread/INFO]: Alice was slain by Zombie";
+
+            assert_eq!(
+                LogEvent::parse(line, &players(&["Alice"])),
+                Some(LogEvent::Death(String::from("Alice")))
+            );
+            assert_eq!(LogEvent::parse(line, &players(&["Bob"])), None);
+        }
+    }
+
+    mod poll {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_skip_existing_lines() {
+            let dir = log_dir("skip");
+            let path = dir.join("latest.log");
+            append(
+                &path,
+                &["[12:00:00] [Server thread/INFO]: Alice joined the game"],
+            );
+            let mut tailer = mock_tailer(&path);
+            tailer.open(true).await.unwrap();
+
+            append(
+                &path,
+                &["[12:00:01] [Server thread/INFO]: Bob joined the game"],
+            );
+            let actual = tailer.poll().await.unwrap();
+
+            assert_eq!(actual, vec![LogEvent::Join(String::from("Bob"))]);
+        }
+
+        #[tokio::test]
+        async fn should_wait_for_complete_lines() {
+            let dir = log_dir("partial");
+            let path = dir.join("latest.log");
+            let mut tailer = mock_tailer(&path);
+
+            std_fs::write(&path, "[12:00:00] [Server thread/INFO]: Alice joined").unwrap();
+            assert!(tailer.poll().await.unwrap().is_empty());
+
+            append(&path, &[" the game"]);
+            assert_eq!(tailer.poll().await.unwrap().len(), 1);
+        }
+
+        #[tokio::test]
+        async fn should_follow_rotation() {
+            let dir = log_dir("rotation");
+            let path = dir.join("latest.log");
+            append(
+                &path,
+                &["[12:00:00] [Server thread/INFO]: Alice joined the game"],
+            );
+            let mut tailer = mock_tailer(&path);
+            tailer.poll().await.unwrap();
+
+            append(
+                &path,
+                &["[12:00:01] [Server thread/INFO]: Alice left the game"],
+            );
+            std_fs::rename(&path, dir.join("2020-05-01-1.log")).unwrap();
+            append(
+                &path,
+                &["[00:00:00] [Server thread/INFO]: Bob joined the game"],
+            );
+            let actual = tailer.poll().await.unwrap();
+
+            assert_eq!(
+                actual,
+                vec![
+                    LogEvent::Leave(String::from("Alice")),
+                    LogEvent::Join(String::from("Bob")),
+                ]
+            );
+            assert_eq!(tailer.metrics.joins.with_label_values(&["Alice"]).get(), 1);
+        }
+
+        #[tokio::test]
+        async fn should_end_sessions_on_stop() {
+            let dir = log_dir("stop");
+            let path = dir.join("latest.log");
+            let mut tailer = mock_tailer(&path);
+            let registry = Registry::new();
+            tailer.online_players.register(&registry).unwrap();
+
+            append(
+                &path,
+                &[
+                    "[12:00:00] [Server thread/INFO]: Alice joined the game",
+                    "[12:00:01] [Server thread/INFO]: Stopping server",
+                ],
+            );
+            tailer.poll().await.unwrap();
+
+            assert_eq!(player_online(&registry, "Alice"), 0.0);
+        }
+
+        #[tokio::test]
+        async fn should_end_sessions_on_start() {
+            let dir = log_dir("start-sessions");
+            let path = dir.join("latest.log");
+            let mut tailer = mock_tailer(&path);
+            let registry = Registry::new();
+            tailer.online_players.register(&registry).unwrap();
+
+            append(
+                &path,
+                &["[12:00:00] [Server thread/INFO]: Alice joined the game"],
+            );
+            tailer.poll().await.unwrap();
+            assert_eq!(player_online(&registry, "Alice"), 1.0);
+
+            std_fs::rename(&path, dir.join("2020-05-01-1.log")).unwrap();
+            append(
+                &path,
+                &["[12:05:00] [Server thread/INFO]: Starting minecraft server version 1.16.5"],
+            );
+            tailer.poll().await.unwrap();
+
+            assert_eq!(player_online(&registry, "Alice"), 0.0);
+        }
+
+        #[tokio::test]
+        async fn should_keep_sessions_on_midnight_rotation() {
+            let dir = log_dir("midnight-sessions");
+            let path = dir.join("latest.log");
+            let mut tailer = mock_tailer(&path);
+            let registry = Registry::new();
+            tailer.online_players.register(&registry).unwrap();
+
+            append(
+                &path,
+                &["[23:59:00] [Server thread/INFO]: Alice joined the game"],
+            );
+            tailer.poll().await.unwrap();
+
+            std_fs::rename(&path, dir.join("2020-05-01-1.log")).unwrap();
+            append(
+                &path,
+                &["[00:00:01] [Server thread/INFO]: <Alice> happy new day"],
+            );
+            tailer.poll().await.unwrap();
+            assert_eq!(player_online(&registry, "Alice"), 1.0);
+
+            append(
+                &path,
+                &["[00:10:00] [Server thread/INFO]: Alice left the game"],
+            );
+            tailer.poll().await.unwrap();
+            assert_eq!(player_online(&registry, "Alice"), 0.0);
+        }
+    }
+}
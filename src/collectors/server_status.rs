@@ -0,0 +1,187 @@
+use crate::Result;
+use prometheus::{GaugeVec, Opts, Registry};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+/// What a status ping of a Java or Bedrock server reported
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    pub online: f64,
+    pub max: f64,
+    pub protocol: f64,
+    pub version: String,
+    pub motd: String,
+    /// Only reported by Bedrock servers
+    pub game_mode: Option<String>,
+    pub latency: Duration,
+}
+
+/// `mc_server_*` gauges shared by all status collectors, distinguished by the `edition` label
+pub struct ServerStatus {
+    up: GaugeVec,
+    players_online: GaugeVec,
+    players_max: GaugeVec,
+    protocol_version: GaugeVec,
+    latency: GaugeVec,
+    info: GaugeVec,
+    /// Info labels last set per edition, replaced once version or motd change
+    info_labels: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl ServerStatus {
+    pub fn new() -> Result<Self> {
+        let gauge =
+            |name: &str, help: &str, labels: &[&str]| GaugeVec::new(Opts::new(name, help), labels);
+
+        Ok(Self {
+            up: gauge(
+                "mc_server_up",
+                "whether the last status ping succeeded",
+                &["edition"],
+            )?,
+            players_online: gauge(
+                "mc_server_players_online",
+                "players currently online",
+                &["edition"],
+            )?,
+            players_max: gauge(
+                "mc_server_players_max",
+                "maximum players allowed online",
+                &["edition"],
+            )?,
+            protocol_version: gauge(
+                "mc_server_protocol_version",
+                "protocol version of the server",
+                &["edition"],
+            )?,
+            latency: gauge(
+                "mc_server_ping_latency_seconds",
+                "round trip time of the status ping",
+                &["edition"],
+            )?,
+            info: gauge(
+                "mc_server_info",
+                "server version and message of the day",
+                &["edition", "version", "motd", "game_mode"],
+            )?,
+            info_labels: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.up.clone()))?;
+        registry.register(Box::new(self.players_online.clone()))?;
+        registry.register(Box::new(self.players_max.clone()))?;
+        registry.register(Box::new(self.protocol_version.clone()))?;
+        registry.register(Box::new(self.latency.clone()))?;
+        registry.register(Box::new(self.info.clone()))?;
+
+        Ok(())
+    }
+
+    /// Sets the gauges of `edition`, a failed ping only marks the server as down
+    pub fn update(&self, edition: &str, status: Option<&Status>) {
+        let status = match status {
+            Some(status) => status,
+            None => {
+                self.up.with_label_values(&[edition]).set(0.0);
+                return;
+            }
+        };
+
+        self.up.with_label_values(&[edition]).set(1.0);
+        self.players_online
+            .with_label_values(&[edition])
+            .set(status.online);
+        self.players_max
+            .with_label_values(&[edition])
+            .set(status.max);
+        self.protocol_version
+            .with_label_values(&[edition])
+            .set(status.protocol);
+        self.latency
+            .with_label_values(&[edition])
+            .set(status.latency.as_secs_f64());
+
+        let labels = vec![
+            String::from(edition),
+            status.version.clone(),
+            status.motd.clone(),
+            status.game_mode.clone().unwrap_or_default(),
+        ];
+        let mut info_labels = self.info_labels.lock().unwrap();
+        if let Some(previous) = info_labels.get(edition) {
+            if *previous != labels {
+                let previous: Vec<&str> = previous.iter().map(|l| l.as_str()).collect();
+                let _ = self.info.remove_label_values(&previous);
+            }
+        }
+
+        let values: Vec<&str> = labels.iter().map(|l| l.as_str()).collect();
+        self.info.with_label_values(&values).set(1.0);
+        info_labels.insert(String::from(edition), labels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock_status(version: &str) -> Status {
+        Status {
+            online: 3.0,
+            max: 20.0,
+            protocol: 754.0,
+            version: String::from(version),
+            motd: String::from("A Minecraft Server"),
+            game_mode: None,
+            latency: Duration::from_millis(20),
+        }
+    }
+
+    mod update {
+        use super::*;
+
+        #[test]
+        fn should_set_gauges() {
+            let status = ServerStatus::new().unwrap();
+
+            status.update("java", Some(&mock_status("1.16.5")));
+
+            assert_eq!(status.up.with_label_values(&["java"]).get(), 1.0);
+            assert_eq!(
+                status.players_online.with_label_values(&["java"]).get(),
+                3.0
+            );
+            assert_eq!(status.latency.with_label_values(&["java"]).get(), 0.02);
+        }
+
+        #[test]
+        fn should_mark_failed_pings_as_down() {
+            let status = ServerStatus::new().unwrap();
+            status.update("java", Some(&mock_status("1.16.5")));
+
+            status.update("java", None);
+
+            assert_eq!(status.up.with_label_values(&["java"]).get(), 0.0);
+        }
+
+        #[test]
+        fn should_replace_info_labels() {
+            let status = ServerStatus::new().unwrap();
+            let registry = Registry::new();
+            status.register(&registry).unwrap();
+
+            status.update("java", Some(&mock_status("1.16.4")));
+            status.update("java", Some(&mock_status("1.16.5")));
+            status.update("bedrock", Some(&mock_status("1.16.201")));
+
+            let families = registry.gather();
+            let info = families
+                .iter()
+                .find(|family| family.get_name() == "mc_server_info")
+                .unwrap();
+
+            assert_eq!(info.get_metric().len(), 2);
+        }
+    }
+}
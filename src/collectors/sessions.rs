@@ -0,0 +1,149 @@
+use crate::Result;
+use prometheus::{Gauge, GaugeVec, Histogram, HistogramOpts, Opts, Registry};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Session lengths from a minute up to a long day of playing
+const DURATION_BUCKETS: [f64; 9] = [
+    60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0, 14400.0, 28800.0, 57600.0,
+];
+
+/// Times the sessions of players coming online and going offline
+pub struct Sessions {
+    duration: Histogram,
+    peak: Gauge,
+    last_join: GaugeVec,
+    /// Start of running sessions, `None` if it started before the exporter
+    started: Mutex<HashMap<String, Option<SystemTime>>>,
+}
+
+impl Sessions {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            duration: Histogram::with_opts(
+                HistogramOpts::new(
+                    "mc_player_session_duration_seconds",
+                    "length of finished player sessions",
+                )
+                .buckets(DURATION_BUCKETS.to_vec()),
+            )?,
+            peak: Gauge::new(
+                "mc_server_players_peak",
+                "most players online at the same time since the exporter started",
+            )?,
+            last_join: GaugeVec::new(
+                Opts::new(
+                    "mc_player_last_join_timestamp_seconds",
+                    "time the player last joined",
+                ),
+                &["player"],
+            )?,
+            started: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.duration.clone()))?;
+        registry.register(Box::new(self.peak.clone()))?;
+        registry.register(Box::new(self.last_join.clone()))?;
+
+        Ok(())
+    }
+
+    /// Starts a session at `at`, if known, with `online` players online including `player`
+    pub fn start(&self, player: &str, at: Option<SystemTime>, online: usize) {
+        self.started
+            .lock()
+            .unwrap()
+            .insert(String::from(player), at);
+
+        if let Some(at) = at {
+            if let Ok(since_epoch) = at.duration_since(UNIX_EPOCH) {
+                self.last_join
+                    .with_label_values(&[player])
+                    .set(since_epoch.as_secs_f64());
+            }
+        }
+
+        if online as f64 > self.peak.get() {
+            self.peak.set(online as f64);
+        }
+    }
+
+    /// Ends the session, only sessions with a known start are timed
+    pub fn end(&self, player: &str, at: SystemTime) {
+        if let Some(Some(started)) = self.started.lock().unwrap().remove(player) {
+            if let Ok(duration) = at.duration_since(started) {
+                self.duration.observe(duration.as_secs_f64());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    mod start {
+        use super::*;
+
+        #[test]
+        fn should_track_peak() {
+            let sessions = Sessions::new().unwrap();
+
+            sessions.start("Alice", Some(at(100)), 1);
+            sessions.start("Bob", Some(at(200)), 2);
+            sessions.end("Alice", at(400));
+            sessions.start("Carol", Some(at(500)), 2);
+
+            assert_eq!(sessions.peak.get(), 2.0);
+        }
+
+        #[test]
+        fn should_set_last_join() {
+            let sessions = Sessions::new().unwrap();
+
+            sessions.start("Alice", Some(at(100)), 1);
+            sessions.end("Alice", at(200));
+            sessions.start("Alice", Some(at(300)), 1);
+
+            assert_eq!(
+                sessions.last_join.with_label_values(&["Alice"]).get(),
+                300.0
+            );
+        }
+    }
+
+    mod end {
+        use super::*;
+
+        #[test]
+        fn should_observe_duration() {
+            let sessions = Sessions::new().unwrap();
+            sessions.start("Alice", Some(at(100)), 1);
+
+            sessions.end("Alice", at(400));
+
+            assert_eq!(sessions.duration.get_sample_count(), 1);
+            assert_eq!(sessions.duration.get_sample_sum(), 300.0);
+        }
+
+        #[test]
+        fn should_not_time_sessions_started_before_tracking() {
+            let sessions = Sessions::new().unwrap();
+            sessions.start("Alice", None, 1);
+
+            sessions.end("Alice", at(500));
+
+            assert_eq!(sessions.duration.get_sample_count(), 0);
+        }
+    }
+}
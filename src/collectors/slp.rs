@@ -0,0 +1,342 @@
+use crate::collectors::{server_status::Status, strip_formatting};
+use crate::Result;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Servers answering the status but not the ping are still reported, just without a ping latency
+const PONG_TIMEOUT: Duration = Duration::from_secs(1);
+/// Status responses include the favicon, but anything this large is not a status
+const MAX_PACKET: usize = 1 << 21;
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    version: Version,
+    players: Players,
+    #[serde(default)]
+    description: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct Version {
+    name: String,
+    protocol: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Players {
+    max: i64,
+    online: i64,
+}
+
+/// Asks a Java edition server for its status like the multiplayer screen does
+pub struct ServerListPing {
+    host: String,
+    port: u16,
+}
+
+impl ServerListPing {
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: String::from(host),
+            port,
+        }
+    }
+
+    pub async fn ping(&self) -> Result<Status> {
+        let (mut stream, mut status) = time::timeout(TIMEOUT, self.request()).await??;
+
+        // Some servers close the connection or never answer the ping, the status latency is used
+        if let Ok(Ok(latency)) = time::timeout(PONG_TIMEOUT, measure_pong(&mut stream)).await {
+            status.latency = latency;
+        }
+
+        Ok(status)
+    }
+
+    /// Returns the connection to ping on and the status, with the latency of the status request
+    async fn request(&self) -> Result<(TcpStream, Status)> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+
+        let mut handshake = vec![];
+        write_varint(&mut handshake, 0x00);
+        // -1 as protocol version, servers answer with their own
+        write_varint(&mut handshake, -1);
+        write_varint(&mut handshake, self.host.len() as i32);
+        handshake.extend_from_slice(self.host.as_bytes());
+        handshake.extend_from_slice(&self.port.to_be_bytes());
+        write_varint(&mut handshake, 1);
+        write_packet(&mut stream, &handshake).await?;
+
+        let start = Instant::now();
+        write_packet(&mut stream, &[0x00]).await?;
+        let packet = read_packet(&mut stream).await?;
+        let status_latency = start.elapsed();
+
+        if read_varint(&mut packet.as_slice()).await? != 0x00 {
+            Err("Server answered status request with an unexpected packet")?
+        }
+        let mut body = &packet[1..];
+        let length = read_varint(&mut body).await? as usize;
+        if length > body.len() {
+            Err("Status response is shorter than announced")?
+        }
+        let response: Response = serde_json::from_slice(&body[..length])?;
+
+        let status = Status {
+            online: response.players.online as f64,
+            max: response.players.max as f64,
+            protocol: response.version.protocol as f64,
+            version: response.version.name,
+            motd: motd(&response.description),
+            game_mode: None,
+            latency: status_latency,
+        };
+
+        Ok((stream, status))
+    }
+}
+
+async fn measure_pong(stream: &mut TcpStream) -> Result<Duration> {
+    let payload: i64 = 0x6d63_6578;
+    let mut ping = vec![0x01];
+    ping.extend_from_slice(&payload.to_be_bytes());
+
+    let start = Instant::now();
+    write_packet(stream, &ping).await?;
+    let pong = read_packet(stream).await?;
+
+    if pong != ping {
+        Err("Server answered ping with an unexpected packet")?
+    }
+
+    Ok(start.elapsed())
+}
+
+/// Flattens a plain string or chat component to text without formatting codes
+fn motd(description: &Value) -> String {
+    let mut text = String::new();
+    flatten(description, &mut text);
+
+    strip_formatting(&text)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn flatten(component: &Value, text: &mut String) {
+    match component {
+        Value::String(value) => text.push_str(value),
+        Value::Array(components) => components.iter().for_each(|c| flatten(c, text)),
+        Value::Object(fields) => {
+            if let Some(value) = fields.get("text") {
+                flatten(value, text);
+            }
+            if let Some(extra) = fields.get("extra") {
+                flatten(extra, text);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+
+    loop {
+        if value & !0x7f == 0 {
+            buf.push(value as u8);
+            return;
+        }
+
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+async fn read_varint<R: AsyncReadExt + Unpin>(reader: &mut R) -> Result<i32> {
+    let mut value: u32 = 0;
+
+    for i in 0..5 {
+        let byte = reader.read_u8().await?;
+        value |= u32::from(byte & 0x7f) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+
+    Err("VarInt is too long")?
+}
+
+async fn write_packet(stream: &mut TcpStream, data: &[u8]) -> Result<()> {
+    let mut packet = vec![];
+    write_varint(&mut packet, data.len() as i32);
+    packet.extend_from_slice(data);
+
+    stream.write_all(&packet).await?;
+
+    Ok(())
+}
+
+async fn read_packet(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let length = read_varint(stream).await?;
+    if length <= 0 || length as usize > MAX_PACKET {
+        Err(format!("Invalid packet length {}", length))?
+    }
+
+    let mut packet = vec![0; length as usize];
+    stream.read_exact(&mut packet).await?;
+
+    Ok(packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+
+    /// How the fake server reacts to pings
+    #[derive(Clone, Copy, PartialEq)]
+    enum Pong {
+        Answer,
+        Close,
+        /// Keeps the connection open without answering
+        Ignore,
+    }
+
+    /// Answers a single status request with `status`, then reacts to the ping as in `pong`
+    async fn fake_server(status: Value, pong: Pong) -> SocketAddr {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let handshake = read_packet(&mut stream).await.unwrap();
+            assert_eq!(handshake[0], 0x00);
+            assert_eq!(*handshake.last().unwrap(), 1);
+            assert_eq!(read_packet(&mut stream).await.unwrap(), vec![0x00]);
+
+            let json = status.to_string();
+            let mut response = vec![0x00];
+            write_varint(&mut response, json.len() as i32);
+            response.extend_from_slice(json.as_bytes());
+            write_packet(&mut stream, &response).await.unwrap();
+
+            let ping = read_packet(&mut stream).await.unwrap();
+            match pong {
+                Pong::Answer => write_packet(&mut stream, &ping).await.unwrap(),
+                Pong::Close => {}
+                Pong::Ignore => time::delay_for(Duration::from_secs(30)).await,
+            }
+        });
+
+        addr
+    }
+
+    fn mock_status(description: Value) -> Value {
+        json!({
+            "version": {"name": "1.16.5", "protocol": 754},
+            "players": {"max": 20, "online": 3, "sample": []},
+            "description": description,
+        })
+    }
+
+    mod varint {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_round_trip() {
+            for value in [0, 1, 127, 128, 25565, 2_097_151, -1].iter() {
+                let mut buf = vec![];
+                write_varint(&mut buf, *value);
+
+                assert_eq!(read_varint(&mut buf.as_slice()).await.unwrap(), *value);
+            }
+        }
+
+        #[test]
+        fn should_encode_minus_one_in_five_bytes() {
+            let mut buf = vec![];
+            write_varint(&mut buf, -1);
+
+            assert_eq!(buf, vec![0xff, 0xff, 0xff, 0xff, 0x0f]);
+        }
+    }
+
+    mod motd {
+        use super::*;
+
+        #[test]
+        fn should_strip_formatting_codes() {
+            let actual = motd(&json!("§aA §lMinecraft§r Server"));
+
+            assert_eq!(actual, "A Minecraft Server");
+        }
+
+        #[test]
+        fn should_flatten_chat_components() {
+            let actual = motd(&json!({
+                "text": "A ",
+                "extra": [{"text": "Minecraft", "bold": true}, " Server"],
+            }));
+
+            assert_eq!(actual, "A Minecraft Server");
+        }
+    }
+
+    mod ping {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_read_status() {
+            let addr = fake_server(mock_status(json!({"text": "Hello"})), Pong::Answer).await;
+            let slp = ServerListPing::new("127.0.0.1", addr.port());
+
+            let actual = slp.ping().await.unwrap();
+
+            assert_eq!(actual.online, 3.0);
+            assert_eq!(actual.max, 20.0);
+            assert_eq!(actual.protocol, 754.0);
+            assert_eq!(actual.version, "1.16.5");
+            assert_eq!(actual.motd, "Hello");
+        }
+
+        #[tokio::test]
+        async fn should_fall_back_without_pong() {
+            let addr = fake_server(mock_status(json!("Hello")), Pong::Close).await;
+            let slp = ServerListPing::new("127.0.0.1", addr.port());
+
+            assert!(slp.ping().await.is_ok());
+        }
+
+        #[tokio::test]
+        async fn should_fall_back_when_pong_times_out() {
+            let addr = fake_server(mock_status(json!("Hello")), Pong::Ignore).await;
+            let slp = ServerListPing::new("127.0.0.1", addr.port());
+
+            let actual = slp.ping().await.unwrap();
+
+            assert_eq!(actual.online, 3.0);
+            assert!(actual.latency < PONG_TIMEOUT);
+        }
+
+        #[tokio::test]
+        async fn should_fail_without_server() {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            drop(listener);
+
+            assert!(ServerListPing::new("127.0.0.1", port).ping().await.is_err());
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use crate::collectors::Rcon;
+use crate::Result;
+use prometheus::{GaugeVec, Opts, Registry};
+use std::str::FromStr;
+
+/// Commands available to read tick times differ between server software
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TpsFlavor {
+    /// `tps` and `mspt` of Paper and its forks
+    Paper,
+    /// `forge tps`, reporting every dimension
+    Forge,
+}
+
+impl FromStr for TpsFlavor {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "paper" => Ok(TpsFlavor::Paper),
+            "forge" => Ok(TpsFlavor::Forge),
+            _ => Err(format!("Unknown tps flavor {}", value)),
+        }
+    }
+}
+
+/// A value of a tps or mspt command for a dimension and time window
+#[derive(Debug, Clone, PartialEq)]
+struct Sample {
+    dimension: String,
+    window: String,
+    value: f64,
+}
+
+/// Ticks per second and milliseconds per tick read over RCON
+pub struct Tps {
+    flavor: TpsFlavor,
+    tps: GaugeVec,
+    mspt: GaugeVec,
+}
+
+impl Tps {
+    pub fn new(flavor: TpsFlavor) -> Result<Self> {
+        Ok(Self {
+            flavor,
+            tps: GaugeVec::new(
+                Opts::new("mc_server_tps", "ticks per second"),
+                &["dimension", "window"],
+            )?,
+            mspt: GaugeVec::new(
+                Opts::new("mc_server_mspt", "average milliseconds per tick"),
+                &["dimension", "window"],
+            )?,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.tps.clone()))?;
+        registry.register(Box::new(self.mspt.clone()))?;
+
+        Ok(())
+    }
+
+    pub async fn collect(&self, rcon: &Rcon) -> Result<()> {
+        match self.flavor {
+            TpsFlavor::Paper => {
+                let tps = parse_paper_tps(&rcon.command("tps").await?)?;
+                set(&self.tps, &tps);
+
+                let mspt = parse_paper_mspt(&rcon.command("mspt").await?)?;
+                set(&self.mspt, &mspt);
+            }
+            TpsFlavor::Forge => {
+                let (tps, mspt) = parse_forge_tps(&rcon.command("forge tps").await?)?;
+                set(&self.tps, &tps);
+                set(&self.mspt, &mspt);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn set(gauge: &GaugeVec, samples: &[Sample]) {
+    for sample in samples {
+        gauge
+            .with_label_values(&[&sample.dimension, &sample.window])
+            .set(sample.value);
+    }
+}
+
+/// Windows listed between `from last ` and `:`, e.g. `TPS from last 1m, 5m, 15m:`
+fn windows(header: &str) -> Vec<String> {
+    let start = match header.find("from last ") {
+        Some(start) => start + "from last ".len(),
+        None => return vec![],
+    };
+
+    header[start..]
+        .trim_end_matches(':')
+        .split(',')
+        .map(|window| window.trim().to_string())
+        .collect()
+}
+
+/// `TPS from last 1m, 5m, 15m: 20.0, 19.98, *20.0`, values above 20 are capped and marked with `*`
+fn parse_paper_tps(output: &str) -> Result<Vec<Sample>> {
+    let colon = output.rfind(':').ok_or("Could not parse tps output")?;
+    let windows = windows(&output[..=colon]);
+
+    let values = output[colon + 1..]
+        .split(',')
+        .map(|value| value.trim().trim_start_matches('*').parse::<f64>())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| format!("Could not parse tps output {}", output))?;
+
+    if windows.len() != values.len() {
+        Err(format!("Could not parse tps output {}", output))?
+    }
+
+    Ok(windows
+        .into_iter()
+        .zip(values)
+        .map(|(window, value)| Sample {
+            dimension: String::from("overall"),
+            window,
+            value,
+        })
+        .collect())
+}
+
+/// `Server tick times (avg/min/max) from last 5s, 10s, 1m:` followed by a line like
+/// `◴ 1.2/0.8/3.4, 1.1/0.7/4.0, 1.3/0.6/5.1`, only the averages are kept
+fn parse_paper_mspt(output: &str) -> Result<Vec<Sample>> {
+    let colon = output.rfind(':').ok_or("Could not parse mspt output")?;
+    let windows = windows(&output[..=colon]);
+
+    let values = output[colon + 1..]
+        .trim_start_matches(|c: char| !c.is_ascii_digit())
+        .split(',')
+        .map(|times| {
+            times
+                .split('/')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .parse::<f64>()
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| format!("Could not parse mspt output {}", output))?;
+
+    if windows.len() != values.len() {
+        Err(format!("Could not parse mspt output {}", output))?
+    }
+
+    Ok(windows
+        .into_iter()
+        .zip(values)
+        .map(|(window, value)| Sample {
+            dimension: String::from("overall"),
+            window,
+            value,
+        })
+        .collect())
+}
+
+/// One line per dimension like
+/// `Dim minecraft:overworld (minecraft:overworld): Mean tick time: 0.543 ms. Mean TPS: 20.000`
+/// and a last one starting with `Overall`. Means are taken over the last 100 ticks.
+fn parse_forge_tps(output: &str) -> Result<(Vec<Sample>, Vec<Sample>)> {
+    let mut tps = vec![];
+    let mut mspt = vec![];
+
+    // Lines may be joined without separator when sent over RCON
+    let output = output
+        .replace("Dim ", "\nDim ")
+        .replace("Overall", "\nOverall");
+
+    for line in output.lines() {
+        let tick_time = match line.find(": Mean tick time: ") {
+            Some(tick_time) => tick_time,
+            None => continue,
+        };
+
+        let name = line[..tick_time].trim();
+        let dimension = if name.starts_with("Overall") {
+            String::from("overall")
+        } else {
+            let name = name.trim_start_matches("Dim").trim();
+            name.split(" (").next().unwrap_or(name).trim().to_string()
+        };
+
+        let rest = &line[tick_time + ": Mean tick time: ".len()..];
+        let ms = rest.split(" ms").next().unwrap_or_default().trim();
+        let mean_tps = rest
+            .rfind("Mean TPS: ")
+            .map(|start| rest[start + "Mean TPS: ".len()..].trim())
+            .unwrap_or_default();
+
+        let parse = |value: &str| {
+            value
+                .parse::<f64>()
+                .map_err(|_| format!("Could not parse forge tps line {}", line))
+        };
+
+        mspt.push(Sample {
+            dimension: dimension.clone(),
+            window: String::from("100t"),
+            value: parse(ms)?,
+        });
+        tps.push(Sample {
+            dimension,
+            window: String::from("100t"),
+            value: parse(mean_tps)?,
+        });
+    }
+
+    if tps.is_empty() {
+        Err(format!(
+            "Could not parse forge tps output {}",
+            output.trim()
+        ))?
+    }
+
+    Ok((tps, mspt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collectors::rcon::fake::fake_rcon;
+    use std::collections::HashMap;
+
+    const PAPER_TPS: &str = "TPS from last 1m, 5m, 15m: 19.5, *20.0, 20.0";
+    const PAPER_MSPT: &str = "Server tick times (avg/min/max) from last 5s, 10s, 1m:\n◴ 12.3/0.8/40.1, 11.0/0.7/45.2, 9.8/0.6/50.0";
+    const FORGE_TPS: &str = "Dim minecraft:overworld (minecraft:overworld): Mean tick time: 1.250 ms. Mean TPS: 20.000\nDim minecraft:the_nether (minecraft:the_nether): Mean tick time: 0.400 ms. Mean TPS: 20.000\nOverall: Mean tick time: 1.650 ms. Mean TPS: 20.000";
+
+    fn sample(dimension: &str, window: &str, value: f64) -> Sample {
+        Sample {
+            dimension: String::from(dimension),
+            window: String::from(window),
+            value,
+        }
+    }
+
+    mod parse_paper_tps {
+        use super::*;
+
+        #[test]
+        fn should_parse_windows() {
+            let actual = parse_paper_tps(PAPER_TPS).unwrap();
+
+            assert_eq!(
+                actual,
+                vec![
+                    sample("overall", "1m", 19.5),
+                    sample("overall", "5m", 20.0),
+                    sample("overall", "15m", 20.0),
+                ]
+            );
+        }
+
+        #[test]
+        fn should_reject_unknown_command() {
+            assert!(parse_paper_tps("Unknown command. Type \"/help\" for help.").is_err());
+        }
+    }
+
+    mod parse_paper_mspt {
+        use super::*;
+
+        #[test]
+        fn should_keep_averages() {
+            let actual = parse_paper_mspt(PAPER_MSPT).unwrap();
+
+            assert_eq!(
+                actual,
+                vec![
+                    sample("overall", "5s", 12.3),
+                    sample("overall", "10s", 11.0),
+                    sample("overall", "1m", 9.8),
+                ]
+            );
+        }
+    }
+
+    mod parse_forge_tps {
+        use super::*;
+
+        #[test]
+        fn should_parse_dimensions() {
+            let (tps, mspt) = parse_forge_tps(FORGE_TPS).unwrap();
+
+            assert_eq!(tps.len(), 3);
+            assert_eq!(mspt[0], sample("minecraft:overworld", "100t", 1.25));
+            assert_eq!(mspt[1], sample("minecraft:the_nether", "100t", 0.4));
+            assert_eq!(tps[2], sample("overall", "100t", 20.0));
+        }
+
+        #[test]
+        fn should_parse_joined_lines() {
+            let (tps, _) = parse_forge_tps(&FORGE_TPS.replace('\n', "")).unwrap();
+
+            assert_eq!(tps.len(), 3);
+        }
+
+        #[test]
+        fn should_parse_numeric_dimensions() {
+            let (tps, _) =
+                parse_forge_tps("Dim  -1 : Mean tick time: 0.100 ms. Mean TPS: 20.000").unwrap();
+
+            assert_eq!(tps[0].dimension, "-1");
+        }
+    }
+
+    mod collect {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_set_gauges() {
+            let mut outputs = HashMap::new();
+            outputs.insert("tps", PAPER_TPS);
+            outputs.insert("mspt", PAPER_MSPT);
+            let (addr, _) = fake_rcon("secret", outputs, 4096).await;
+            let rcon = Rcon::new("127.0.0.1", addr.port(), "secret").unwrap();
+            let tps = Tps::new(TpsFlavor::Paper).unwrap();
+
+            tps.collect(&rcon).await.unwrap();
+
+            assert_eq!(tps.tps.with_label_values(&["overall", "1m"]).get(), 19.5);
+            assert_eq!(tps.mspt.with_label_values(&["overall", "5s"]).get(), 12.3);
+        }
+    }
+}
@@ -1,35 +1,37 @@
-use hyper::{
-    header::CONTENT_TYPE,
-    service::{make_service_fn, service_fn},
-    Body, Response, Server,
+use collectors::{
+    parse_address, LogTailer, OnlinePlayers, Query, QueryMetrics, RakNetPing, Rcon, RconRules,
+    ServerListPing, ServerStatus, Sessions, Tps, TpsFlavor,
 };
 use player::gather_players;
-use prometheus::{gather, Encoder, TextEncoder};
-use prometheus_handler::track_for_player;
+use prometheus_handler::StatCache;
+use server::{run_server, State, WebConfig};
+use sinks::{Graphite, InfluxDb, Mqtt, MqttConfig, Pushgateway, RemoteWrite, Sink, StatsD};
 use std::env;
 use std::{
     error,
-    net::{IpAddr, SocketAddr},
+    net::IpAddr,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::Arc,
     time::Duration,
 };
-use tokio::{time, try_join};
+use tokio::{sync::watch, time, try_join};
 
 #[macro_use]
 extern crate log;
 #[macro_use]
-extern crate prometheus;
-#[macro_use]
 extern crate lazy_static;
 extern crate hyper;
 extern crate simple_logger;
 
+mod collectors;
 mod player;
 mod prometheus_handler;
+mod server;
+mod sinks;
 mod stats;
 
-pub type Result<T> = std::result::Result<T, Box<dyn error::Error>>;
+pub type Result<T> = std::result::Result<T, Box<dyn error::Error + Send + Sync>>;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -41,21 +43,348 @@ async fn main() -> Result<()> {
 
     let ip = env::var("HOST_IP").unwrap_or(String::from("0.0.0.0"));
     let addr = (ip.parse::<IpAddr>()?, 8000).into();
+    let concurrency = match env::var("SCRAPE_CONCURRENCY") {
+        Ok(c) => c
+            .parse::<usize>()
+            .map_err(|_| "Could not parse SCRAPE_CONCURRENCY")?,
+        Err(_) => 4,
+    };
+    let merge_metrics = match env::var("MERGE_METRICS") {
+        Ok(m) => bool::from_str(&m).map_err(|_| "Could not parse MERGE_METRICS")?,
+        Err(_) => false,
+    };
+
+    let web_config = match env::var("WEB_CONFIG_FILE") {
+        Ok(file) => WebConfig::from_file(Path::new(&file))?,
+        Err(_) => WebConfig::default(),
+    };
+
+    let pushgateway = match env::var("PUSHGATEWAY_URL") {
+        Ok(url) => {
+            let job = env::var("PUSHGATEWAY_JOB").unwrap_or(String::from("minecraft"));
+            let instance = env::var("PUSHGATEWAY_INSTANCE").unwrap_or(world_name(&path));
+            Some(Pushgateway::new(&url, &job, &instance)?)
+        }
+        Err(_) => None,
+    };
+
+    let remote_write = match env::var("REMOTE_WRITE_URL") {
+        Ok(url) => {
+            let tenant = env::var("REMOTE_WRITE_TENANT").ok();
+            let labels = vec![
+                (String::from("job"), String::from("minecraft")),
+                (String::from("instance"), world_name(&path)),
+            ];
+            let mut remote_write = RemoteWrite::new(&url, tenant, labels)?;
+            if let Ok(max) = env::var("REMOTE_WRITE_MAX_SAMPLES_PER_SEND") {
+                remote_write.max_samples_per_send = max
+                    .parse::<usize>()
+                    .map_err(|_| "Could not parse REMOTE_WRITE_MAX_SAMPLES_PER_SEND")?;
+            }
+            Some(remote_write)
+        }
+        Err(_) => None,
+    };
+
+    let mut sinks = vec![];
+    if let Ok(url) = env::var("INFLUXDB_URL") {
+        let token = env::var("INFLUXDB_TOKEN").ok();
+        sinks.push(Sink::InfluxDb(InfluxDb::new(&url, token)?));
+    }
+    if let Ok(addr) = env::var("GRAPHITE_ADDRESS") {
+        let prefix = env::var("GRAPHITE_PREFIX").unwrap_or(String::from("minecraft"));
+        sinks.push(Sink::Graphite(Graphite::new(&addr, &prefix)));
+    }
+
+    let mqtt = match env::var("MQTT_ADDRESS") {
+        Ok(addr) => Some(Mqtt::new(MqttConfig {
+            addr,
+            client_id: env::var("MQTT_CLIENT_ID").unwrap_or(String::from("rs-minecraft-exporter")),
+            username: env::var("MQTT_USERNAME").ok(),
+            password: env::var("MQTT_PASSWORD").ok(),
+            world: world_name(&path),
+            qos: match env::var("MQTT_QOS") {
+                Ok(q) => q.parse::<u8>().map_err(|_| "Could not parse MQTT_QOS")?,
+                Err(_) => 0,
+            },
+            retain: match env::var("MQTT_RETAIN") {
+                Ok(r) => bool::from_str(&r).map_err(|_| "Could not parse MQTT_RETAIN")?,
+                Err(_) => true,
+            },
+            home_assistant: match env::var("MQTT_HOME_ASSISTANT") {
+                Ok(h) => bool::from_str(&h).map_err(|_| "Could not parse MQTT_HOME_ASSISTANT")?,
+                Err(_) => false,
+            },
+        })?),
+        Err(_) => None,
+    };
+
+    let statsd = match env::var("STATSD_ADDRESS") {
+        Ok(addr) => {
+            let prefix = env::var("STATSD_PREFIX").unwrap_or(String::from("minecraft"));
+            let tags = match env::var("STATSD_TAGS") {
+                Ok(t) => bool::from_str(&t).map_err(|_| "Could not parse STATSD_TAGS")?,
+                Err(_) => true,
+            };
+            Some(StatsD::new(&addr, &prefix, tags))
+        }
+        Err(_) => None,
+    };
+
+    let server_list_ping = match env::var("SERVER_ADDRESS") {
+        Ok(address) => {
+            let (host, port) = parse_address(&address, 25565)?;
+            Some(ServerListPing::new(&host, port))
+        }
+        Err(_) => None,
+    };
+
+    let raknet_ping = match env::var("BEDROCK_ADDRESS") {
+        Ok(address) => {
+            let (host, port) = parse_address(&address, 19132)?;
+            Some(RakNetPing::new(&host, port))
+        }
+        Err(_) => None,
+    };
+    let query = match env::var("QUERY_ADDRESS") {
+        Ok(address) => {
+            let (host, port) = parse_address(&address, 25565)?;
+            Some(Query::new(&host, port))
+        }
+        Err(_) => None,
+    };
+
+    let rcon = match env::var("RCON_ADDRESS") {
+        Ok(address) => {
+            let (host, port) = parse_address(&address, 25575)?;
+            let password = env::var("RCON_PASSWORD").map_err(|_| "RCON_PASSWORD is not set")?;
+            Some(Arc::new(Rcon::new(&host, port, &password)?))
+        }
+        Err(_) => None,
+    };
+    let tps = match env::var("RCON_TPS") {
+        Ok(t) if t == "none" => None,
+        Ok(t) => Some(TpsFlavor::from_str(&t).map_err(|_| "Could not parse RCON_TPS")?),
+        Err(_) => None,
+    };
+    let rcon_rules = match env::var("RCON_RULES_FILE") {
+        Ok(file) if rcon.is_some() => Some(RconRules::from_file(Path::new(&file))?),
+        Ok(_) => Err("RCON_RULES_FILE requires RCON_ADDRESS")?,
+        Err(_) => None,
+    };
+
+    let stat_cache = StatCache::new()?;
+    let state = Arc::new(State::new(
+        stat_cache.clone(),
+        merge_metrics,
+        path.clone(),
+        web_config,
+    )?);
+    let scrape_state = state.clone();
+
+    let log_file = env::var("LOG_FILE").ok();
+    // Joins and leaves from the log and the query player list end up in the same sessions
+    let online_players = if log_file.is_some() || query.is_some() {
+        let sessions = Sessions::new()?;
+        let online_players = Arc::new(OnlinePlayers::new(stat_cache.clone(), Some(sessions))?);
+        online_players.register(&state.registries.game)?;
+        Some(online_players)
+    } else {
+        None
+    };
+
+    if let (Some(file), Some(online_players)) = (log_file, &online_players) {
+        let log_tailer =
+            LogTailer::new(Path::new(&file), stat_cache.clone(), online_players.clone())?;
+        log_tailer.register(&state.registries.game)?;
+
+        tokio::spawn(log_tailer.run());
+    }
+    if let Some(remote_write) = &remote_write {
+        remote_write.register(&state.registries.exporter)?;
+    }
+
+    // Both editions share the `mc_server_*` metrics, told apart by the `edition` label
+    let server_status = if server_list_ping.is_some() || raknet_ping.is_some() {
+        let server_status = Arc::new(ServerStatus::new()?);
+        server_status.register(&state.registries.game)?;
+        Some(server_status)
+    } else {
+        None
+    };
+
+    if let (Some(server_list_ping), Some(server_status)) = (server_list_ping, server_status.clone())
+    {
+        tokio::spawn(async move {
+            loop {
+                match server_list_ping.ping().await {
+                    Ok(status) => server_status.update("java", Some(&status)),
+                    Err(e) => {
+                        warn!("Could not ping server: {}", e);
+                        server_status.update("java", None);
+                    }
+                }
+
+                time::delay_for(Duration::from_secs(5)).await;
+            }
+        });
+    }
+
+    if let (Some(raknet_ping), Some(server_status)) = (raknet_ping, server_status) {
+        tokio::spawn(async move {
+            loop {
+                match raknet_ping.ping().await {
+                    Ok(status) => server_status.update("bedrock", Some(&status)),
+                    Err(e) => {
+                        warn!("Could not ping bedrock server: {}", e);
+                        server_status.update("bedrock", None);
+                    }
+                }
+
+                time::delay_for(Duration::from_secs(5)).await;
+            }
+        });
+    }
+
+    if let (Some(query), Some(online_players)) = (query, online_players) {
+        let query_metrics = QueryMetrics::new(online_players)?;
+        query_metrics.register(&state.registries.game)?;
+
+        tokio::spawn(async move {
+            loop {
+                match query.full_stat().await {
+                    Ok(stat) => query_metrics.update(Some(&stat)),
+                    Err(e) => {
+                        warn!("Could not query server: {}", e);
+                        query_metrics.update(None);
+                    }
+                }
+
+                time::delay_for(Duration::from_secs(5)).await;
+            }
+        });
+    }
+
+    if let Some(rcon) = &rcon {
+        rcon.register(&state.registries.game)?;
+    }
+
+    if let (Some(rcon), Some(flavor)) = (rcon.clone(), tps) {
+        let tps = Tps::new(flavor)?;
+        tps.register(&state.registries.game)?;
+
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = tps.collect(&rcon).await {
+                    warn!("Could not read tps over RCON: {}", e);
+                }
+
+                time::delay_for(Duration::from_secs(5)).await;
+            }
+        });
+    }
+
+    if let (Some(rcon), Some(rcon_rules)) = (&rcon, rcon_rules) {
+        rcon_rules.register(&state.registries.game)?;
+
+        for rule in rcon_rules.rules {
+            let rcon = rcon.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    if let Err(e) = rule.collect(&rcon).await {
+                        warn!("Could not run RCON command `{}`: {}", rule.command, e);
+                    }
+
+                    time::delay_for(rule.interval).await;
+                }
+            });
+        }
+    }
+
+    // Every sink runs on its own task after scrapes, so a slow endpoint only delays itself
+    let (scrape_done, scraped) = watch::channel(false);
+
+    if let Some(pushgateway) = pushgateway {
+        let mut scraped = scraped.clone();
+        let registries = state.registries.clone();
+
+        tokio::spawn(async move {
+            while next_scrape(&mut scraped).await {
+                if let Err(e) = pushgateway.push(&registries.gather_all()).await {
+                    error!("Could not push metrics: {}", e);
+                }
+            }
+        });
+    }
+
+    if let Some(remote_write) = remote_write {
+        let mut scraped = scraped.clone();
+        let registries = state.registries.clone();
+
+        tokio::spawn(async move {
+            while next_scrape(&mut scraped).await {
+                remote_write.send(&registries.gather_game()).await;
+            }
+        });
+    }
+
+    if let Some(mqtt) = mqtt {
+        let mut scraped = scraped.clone();
+        let stat_cache = stat_cache.clone();
+
+        tokio::spawn(async move {
+            while next_scrape(&mut scraped).await {
+                if let Err(e) = mqtt.publish(&stat_cache.snapshot()).await {
+                    error!("Could not publish to MQTT: {}", e);
+                }
+            }
+        });
+    }
+
+    if let Some(statsd) = statsd {
+        let mut scraped = scraped.clone();
+        let stat_cache = stat_cache.clone();
+
+        tokio::spawn(async move {
+            while next_scrape(&mut scraped).await {
+                if let Err(e) = statsd.send(&stat_cache.snapshot()).await {
+                    error!("Could not send metrics to StatsD: {}", e);
+                }
+            }
+        });
+    }
+
+    for sink in sinks {
+        let mut scraped = scraped.clone();
+        let registries = state.registries.clone();
+
+        tokio::spawn(async move {
+            while next_scrape(&mut scraped).await {
+                if let Err(e) = sink.write(&registries.gather_game()).await {
+                    error!("Could not write metrics to {}: {}", sink.name(), e);
+                }
+            }
+        });
+    }
 
     let scrape = tokio::spawn(async move {
         loop {
             trace!("Scraping player Metrics ...");
-            if let Err(e) = gather_metrics(&path).await {
+            if let Err(e) = gather_metrics(&path, concurrency, &stat_cache).await {
                 error!("Scraping error: {}", e);
                 panic!("Scrape broken, terminating...");
             }
+            scrape_state.set_scraped();
+            // Fails only without any sink waiting for scrapes
+            let _ = scrape_done.broadcast(true);
 
             time::delay_for(Duration::from_secs(5)).await;
         }
     });
 
     let server = tokio::spawn(async move {
-        if let Err(e) = run_server(addr).await {
+        if let Err(e) = run_server(addr, state).await {
             error!("server error: {}", e);
             panic!("server broken, terminating...");
         }
@@ -68,20 +397,6 @@ async fn main() -> Result<()> {
     }
 }
 
-async fn run_server(addr: SocketAddr) -> Result<()> {
-    info!("Listening on http://{}", addr);
-
-    let make_svc = make_service_fn(move |_| async move {
-        Ok::<_, hyper::Error>(service_fn(move |_req| async move { serve_req().await }))
-    });
-
-    // Then bind and serve...
-    Server::bind(&addr)
-        .serve(make_svc)
-        .await
-        .map_err(|e| e.into())
-}
-
 fn handle_args(args: Vec<String>) -> Result<(PathBuf, log::Level)> {
     match args.len() {
         0 => Err("No arguments given")?,
@@ -109,24 +424,28 @@ fn check_path(path: String) -> Result<PathBuf> {
     }
 }
 
-async fn serve_req() -> std::result::Result<Response<Body>, hyper::http::Error> {
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    let metric_families = gather();
-    encoder.encode(&metric_families, &mut buffer).unwrap();
-
-    Response::builder()
-        .status(200)
-        .header(CONTENT_TYPE, encoder.format_type())
-        .body(Body::from(buffer))
+fn world_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
 }
 
-async fn gather_metrics(path: &Path) -> Result<()> {
-    let players = gather_players(path).await?;
-
-    for player in &players {
-        track_for_player(&player).await?;
+/// Waits for a scrape finished since the previous call, scrapes finished in between are skipped.
+/// Returns `false` once scraping stopped.
+async fn next_scrape(scraped: &mut watch::Receiver<bool>) -> bool {
+    loop {
+        match scraped.recv().await {
+            Some(true) => return true,
+            // Initial value before the first scrape
+            Some(false) => continue,
+            None => return false,
+        }
     }
+}
+
+async fn gather_metrics(path: &Path, concurrency: usize, stat_cache: &StatCache) -> Result<()> {
+    let players = gather_players(path, concurrency).await?;
+    stat_cache.update(players);
 
     Ok(())
 }
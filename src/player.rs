@@ -3,15 +3,55 @@ use crate::Result;
 use fs::{DirEntry, File};
 use nbt;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::{
     fs,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::{
+    sync::{Mutex, Semaphore},
+    task,
 };
-use tokio::sync::Mutex;
 
 lazy_static! {
     static ref PLAYER_NAMES: Mutex<HashMap<String, String>> = { Mutex::new(HashMap::new()) };
+    static ref FILE_STATES: Mutex<HashMap<String, PlayerFiles>> = Mutex::new(HashMap::new());
+}
+
+/// Last seen modification time and size of a single file
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FileState {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl FileState {
+    fn of(path: &Path) -> Result<Self> {
+        let meta = fs::metadata(path)?;
+
+        Ok(Self {
+            modified: meta.modified()?,
+            len: meta.len(),
+        })
+    }
+}
+
+/// File states of a players stats and playerdata file
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PlayerFiles {
+    stats: FileState,
+    nbt: FileState,
+}
+
+impl PlayerFiles {
+    fn of(stats_path: &Path, nbt_path: &Path) -> Result<Self> {
+        Ok(Self {
+            stats: FileState::of(stats_path)?,
+            nbt: FileState::of(nbt_path)?,
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -25,18 +65,12 @@ pub struct Player {
 impl Player {
     pub async fn from_uuid(uuid: String, stats_path: &PathBuf, nbt_path: &PathBuf) -> Result<Self> {
         let name = get_player_name(&uuid).await?;
-        let file_name = format!("{}.json", uuid);
-        let path = stats_path.join(file_name);
-
-        let stats = {
-            let s = fs::read_to_string(path)?;
-            Stats::from(s)?
-        };
+        let path = stats_file(stats_path, &uuid);
+        let nbt_path = nbt_path.clone();
 
-        let nbt_stats = {
-            let n = File::open(nbt_path)?;
-            nbt::de::from_gzip_reader(n)?
-        };
+        // Reading and decoding is blocking, keep it away from the async runtime
+        let (stats, nbt_stats) =
+            task::spawn_blocking(move || read_player_files(path, nbt_path)).await??;
 
         Ok(Self {
             uuid,
@@ -47,6 +81,24 @@ impl Player {
     }
 }
 
+fn read_player_files(stats_path: PathBuf, nbt_path: PathBuf) -> Result<(Stats, NbtStats)> {
+    let stats = {
+        let s = fs::read_to_string(stats_path)?;
+        Stats::from(s)?
+    };
+
+    let nbt_stats = {
+        let n = File::open(nbt_path)?;
+        nbt::de::from_gzip_reader(n)?
+    };
+
+    Ok((stats, nbt_stats))
+}
+
+fn stats_file(stats_path: &PathBuf, uuid: &str) -> PathBuf {
+    stats_path.join(format!("{}.json", uuid))
+}
+
 #[derive(Debug, Deserialize)]
 struct NameResponse {
     name: String,
@@ -90,7 +142,24 @@ async fn fetch_from_mojang(uuid: &String) -> Result<String> {
     }
 }
 
-pub async fn gather_players(base_path: &Path) -> Result<Vec<Player>> {
+/// Reads all players of the world whose files changed since the last call, parsing at most
+/// `concurrency` players at once
+pub async fn gather_players(base_path: &Path, concurrency: usize) -> Result<Vec<Player>> {
+    // Not kept locked while parsing, concurrent scrapes at worst parse the same files twice
+    let mut file_states = FILE_STATES.lock().await.clone();
+    let players = gather_changed_players(base_path, concurrency, &mut file_states).await?;
+    *FILE_STATES.lock().await = file_states;
+
+    Ok(players)
+}
+
+/// Reads the players whose files differ from `file_states` and updates it to the files read,
+/// players no longer in `playerdata` are dropped from it
+async fn gather_changed_players(
+    base_path: &Path,
+    concurrency: usize,
+    file_states: &mut HashMap<String, PlayerFiles>,
+) -> Result<Vec<Player>> {
     let stats_path = base_path.join(Path::new("stats"));
 
     if !stats_path.exists() {
@@ -107,6 +176,9 @@ pub async fn gather_players(base_path: &Path) -> Result<Vec<Player>> {
     };
 
     let mut result: Vec<Player> = vec![];
+    let mut present = HashSet::new();
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = vec![];
 
     for entry in playerdata {
         let nbt_file: DirEntry = entry?;
@@ -114,16 +186,44 @@ pub async fn gather_players(base_path: &Path) -> Result<Vec<Player>> {
         let entry = Path::new(entry).file_stem();
 
         if let Some(entry) = entry.and_then(|e| e.to_str()) {
-            let player: Result<Player> =
-                Player::from_uuid(String::from(entry), &stats_path, &nbt_file.path()).await;
-
-            match player {
-                Ok(player) => {
-                    result.push(player);
-                }
+            let files = match PlayerFiles::of(&stats_file(&stats_path, entry), &nbt_file.path()) {
+                Ok(files) => files,
                 Err(e) => {
-                    error!("{}", e);
+                    error!("Could not read file metadata for {}: {}", entry, e);
+                    continue;
                 }
+            };
+            present.insert(String::from(entry));
+
+            if file_states.get(entry) == Some(&files) {
+                trace!("Files unchanged for {}, skipping", entry);
+                continue;
+            }
+
+            let uuid = String::from(entry);
+            let stats_path = stats_path.clone();
+            let semaphore = semaphore.clone();
+
+            tasks.push(task::spawn(async move {
+                let _permit = semaphore.acquire().await;
+                let player = Player::from_uuid(uuid, &stats_path, &nbt_file.path()).await;
+
+                (files, player)
+            }));
+        }
+    }
+
+    // Deleted player files would otherwise be remembered forever
+    file_states.retain(|uuid, _| present.contains(uuid));
+
+    for task in tasks {
+        match task.await? {
+            (files, Ok(player)) => {
+                file_states.insert(player.uuid.clone(), files);
+                result.push(player);
+            }
+            (_, Err(e)) => {
+                error!("{}", e);
             }
         }
     }
@@ -134,11 +234,145 @@ pub async fn gather_players(base_path: &Path) -> Result<Vec<Player>> {
 #[macro_export]
 macro_rules! mock_player {
     ($id:expr) => {
+        crate::mock_player!($id, crate::mock_stats!())
+    };
+    ($id:expr, $stats:expr) => {
         Player {
             name: format!("name-{}", $id),
             nbt_stats: crate::mock_nbt!(),
             uuid: format!("{}", $id),
-            stats: crate::mock_stats!(),
+            stats: $stats,
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, time::Duration};
+
+    /// World with a stats and playerdata folder for the players with `uuids`
+    async fn mock_world(name: &str, uuids: &[&str]) -> PathBuf {
+        let dir =
+            env::temp_dir().join(format!("mc-exporter-world-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("stats")).unwrap();
+        fs::create_dir_all(dir.join("playerdata")).unwrap();
+
+        let mut names = PLAYER_NAMES.lock().await;
+        for uuid in uuids {
+            names.insert(uuid.to_string(), format!("name-{}", uuid));
+            write_player(&dir, uuid);
+        }
+
+        dir
+    }
+
+    fn write_player(dir: &Path, uuid: &str) {
+        fs::write(
+            dir.join("stats").join(format!("{}.json", uuid)),
+            r#"{"stats": {"minecraft:custom": {"minecraft:jump": 42}}}"#,
+        )
+        .unwrap();
+
+        let mut nbt = File::create(dir.join("playerdata").join(format!("{}.dat", uuid))).unwrap();
+        nbt::ser::to_gzip_writer(&mut nbt, &crate::mock_nbt!(), None).unwrap();
+    }
+
+    fn uuids(players: &[Player]) -> Vec<String> {
+        let mut uuids: Vec<String> = players.iter().map(|p| p.uuid.clone()).collect();
+        uuids.sort();
+
+        uuids
+    }
+
+    mod gather_changed_players {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_skip_unchanged_files() {
+            let world = mock_world("unchanged", &["unchanged-1", "unchanged-2"]).await;
+            let mut file_states = HashMap::new();
+
+            let first = gather_changed_players(&world, 4, &mut file_states)
+                .await
+                .unwrap();
+            let second = gather_changed_players(&world, 4, &mut file_states)
+                .await
+                .unwrap();
+
+            assert_eq!(uuids(&first), vec!["unchanged-1", "unchanged-2"]);
+            assert!(second.is_empty());
+        }
+
+        #[tokio::test]
+        async fn should_reread_files_with_changed_size() {
+            let world = mock_world("size", &["size-1", "size-2"]).await;
+            let mut file_states = HashMap::new();
+            gather_changed_players(&world, 4, &mut file_states)
+                .await
+                .unwrap();
+
+            fs::write(
+                world.join("stats").join("size-1.json"),
+                r#"{"stats": {"minecraft:custom": {"minecraft:jump": 1042}}}"#,
+            )
+            .unwrap();
+            let actual = gather_changed_players(&world, 4, &mut file_states)
+                .await
+                .unwrap();
+
+            assert_eq!(uuids(&actual), vec!["size-1"]);
+        }
+
+        #[tokio::test]
+        async fn should_reread_files_with_changed_mtime() {
+            let world = mock_world("mtime", &["mtime-1", "mtime-2"]).await;
+            let mut file_states = HashMap::new();
+            gather_changed_players(&world, 4, &mut file_states)
+                .await
+                .unwrap();
+
+            // Same size, but modified at another time than last seen
+            let files = file_states.get_mut("mtime-2").unwrap();
+            files.nbt.modified -= Duration::from_secs(60);
+            let actual = gather_changed_players(&world, 4, &mut file_states)
+                .await
+                .unwrap();
+
+            assert_eq!(uuids(&actual), vec!["mtime-2"]);
+        }
+
+        #[tokio::test]
+        async fn should_read_other_players_when_one_fails() {
+            let world = mock_world("corrupt", &["corrupt-1", "corrupt-2", "corrupt-3"]).await;
+            fs::write(world.join("playerdata").join("corrupt-2.dat"), "not nbt").unwrap();
+            let mut file_states = HashMap::new();
+
+            let actual = gather_changed_players(&world, 1, &mut file_states)
+                .await
+                .unwrap();
+
+            assert_eq!(uuids(&actual), vec!["corrupt-1", "corrupt-3"]);
+            // Retried on the next scrape
+            assert!(!file_states.contains_key("corrupt-2"));
+        }
+
+        #[tokio::test]
+        async fn should_forget_removed_players() {
+            let world = mock_world("removed", &["removed-1", "removed-2"]).await;
+            let mut file_states = HashMap::new();
+            gather_changed_players(&world, 4, &mut file_states)
+                .await
+                .unwrap();
+
+            fs::remove_file(world.join("playerdata").join("removed-1.dat")).unwrap();
+            gather_changed_players(&world, 4, &mut file_states)
+                .await
+                .unwrap();
+
+            assert!(!file_states.contains_key("removed-1"));
+            assert!(file_states.contains_key("removed-2"));
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use prometheus::proto::{Counter, Gauge, LabelPair, Metric, MetricFamily, MetricType};
+
+pub fn new_family(name: &str, help: &str, field_type: MetricType) -> MetricFamily {
+    let mut family = MetricFamily::default();
+    family.set_name(String::from(name));
+    family.set_help(String::from(help));
+    family.set_field_type(field_type);
+
+    family
+}
+
+pub fn counter_metric(labels: &[(&str, &str)], value: f64) -> Metric {
+    let mut counter = Counter::default();
+    counter.set_value(value);
+
+    let mut metric = labeled_metric(labels);
+    metric.set_counter(counter);
+
+    metric
+}
+
+pub fn gauge_metric(labels: &[(&str, &str)], value: f64) -> Metric {
+    let mut gauge = Gauge::default();
+    gauge.set_value(value);
+
+    let mut metric = labeled_metric(labels);
+    metric.set_gauge(gauge);
+
+    metric
+}
+
+fn labeled_metric(labels: &[(&str, &str)]) -> Metric {
+    let mut metric = Metric::default();
+
+    for (name, value) in labels {
+        let mut label = LabelPair::default();
+        label.set_name(String::from(*name));
+        label.set_value(String::from(*value));
+
+        metric.mut_label().push(label);
+    }
+
+    metric
+}
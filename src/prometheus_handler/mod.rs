@@ -1,15 +1,114 @@
-use crate::player::Player;
-use crate::prometheus_handler::nbt::register_nbt_stats;
 use crate::Result;
-use playerstats::register_playerstats;
+use prometheus::{proto::MetricFamily, Registry};
 
+pub use nbt::NBT_GAUGES;
+pub use stat_cache::{Snapshot, StatCache, StatChange};
+
+mod family;
 mod nbt;
 mod playerstats;
 mod stat_cache;
 
-pub async fn track_for_player(player: &Player) -> Result<()> {
-    register_playerstats(player).await?;
-    register_nbt_stats(player).await?;
+/// Game metrics and metrics about the exporter itself are kept in separate registries.
+/// Player stats are kept apart from the other game metrics, so they can be rendered filtered.
+#[derive(Clone)]
+pub struct Registries {
+    pub game: Registry,
+    pub exporter: Registry,
+    stats: Registry,
+    stat_cache: StatCache,
+}
+
+impl Registries {
+    pub fn new(stat_cache: &StatCache) -> Result<Self> {
+        let game = Registry::new();
+        let exporter = Registry::new();
+        let stats = Registry::new();
+
+        stat_cache.register(&stats, &exporter)?;
+        register_process_collector(&exporter)?;
+
+        Ok(Self {
+            game,
+            exporter,
+            stats,
+            stat_cache: stat_cache.clone(),
+        })
+    }
+
+    /// Player stats and all other game metrics
+    pub fn gather_game(&self) -> Vec<MetricFamily> {
+        let mut families = self.stats.gather();
+        families.extend(self.game.gather());
+        families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+
+        families
+    }
+
+    /// Game metrics of the kept families, player stats are only rendered for the kept players
+    pub fn gather_game_matching(
+        &self,
+        keep_family: &dyn Fn(&str) -> bool,
+        keep_player: &dyn Fn(&str) -> bool,
+    ) -> Vec<MetricFamily> {
+        let mut families = self.stat_cache.collect_matching(keep_family, keep_player);
+        families.extend(
+            self.game
+                .gather()
+                .into_iter()
+                .filter(|family| keep_family(family.get_name()))
+                .filter_map(|family| retain_players(family, keep_player)),
+        );
+        families.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+
+        families
+    }
+
+    pub fn gather_all(&self) -> Vec<MetricFamily> {
+        let mut families = self.gather_game();
+        families.extend(self.exporter.gather());
+
+        families
+    }
+}
+
+/// Drops the series of players not kept, series without a `player` label are kept.
+/// Returns `None` once all series of the family were dropped.
+pub fn retain_players(
+    mut family: MetricFamily,
+    keep_player: &dyn Fn(&str) -> bool,
+) -> Option<MetricFamily> {
+    let before = family.get_metric().len();
+    let metrics: Vec<_> = family
+        .take_metric()
+        .into_iter()
+        .filter(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .find(|label| label.get_name() == "player")
+                .map_or(true, |label| keep_player(label.get_value()))
+        })
+        .collect();
+
+    if metrics.is_empty() && before > 0 {
+        return None;
+    }
+    family.set_metric(metrics.into());
+
+    Some(family)
+}
+
+#[cfg(target_os = "linux")]
+fn register_process_collector(registry: &Registry) -> Result<()> {
+    use prometheus::process_collector::ProcessCollector;
+
+    registry.register(Box::new(ProcessCollector::for_self()))?;
+
+    Ok(())
+}
 
+#[cfg(not(target_os = "linux"))]
+fn register_process_collector(_: &Registry) -> Result<()> {
     Ok(())
 }
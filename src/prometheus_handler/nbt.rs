@@ -1,62 +1,85 @@
-use crate::player::Player;
-use crate::prometheus_handler::stat_cache::STAT_CACHE;
-use crate::Result;
+use crate::prometheus_handler::family::{gauge_metric, new_family};
+use crate::prometheus_handler::stat_cache::Snapshot;
+use crate::stats::NbtStats;
+use prometheus::core::Desc;
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::Result;
+use std::collections::HashMap;
 
-macro_rules! local_register_gauge {
-    // Multiple stats at once
-    ($player:expr,
-        $( [$s_name:expr, $help:expr, $val:expr] )+
-    ) => {
-        $(
-            // Matcher for self, single stat version
-            local_register_gauge!($s_name, $help, $val, $player);
-        )+
-    };
-    // One stat
-    ( $s_name:expr, $help:expr, $val:expr, $player:expr)  => {
-        STAT_CACHE.set_gauge(
-            $player,
-            &String::from($s_name),
-            &String::from($help),
-            $val
-        ).await?;
-    };
+pub struct NbtGauge {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub value: fn(&NbtStats) -> f64,
 }
 
-pub async fn register_nbt_stats(player: &Player) -> Result<()> {
-    local_register_gauge!(
-        player,
-        [
-            // XpTotal
-            "mc_xp_total",
-            "total collceted xp",
-            player.nbt_stats.xp_total
-        ]
-        [
-            // XpLevel
-            "mc_xp_level",
-            "current player level",
-            player.nbt_stats.xp_level
-        ]
-        [
-            // Score
-            "mc_score",
-            "current player score",
-            player.nbt_stats.score
-        ]
-        [
-            // Health
-            "mc_health",
-            "current player health",
-            player.nbt_stats.health
-        ]
-        [
-            // foodLevel
-            "mc_food_level",
-            "current player food level",
-            player.nbt_stats.food_level
-        ]
-    );
+pub const NBT_GAUGES: [NbtGauge; 5] = [
+    NbtGauge {
+        // XpTotal
+        name: "mc_xp_total",
+        help: "total collceted xp",
+        value: |nbt| nbt.xp_total,
+    },
+    NbtGauge {
+        // XpLevel
+        name: "mc_xp_level",
+        help: "current player level",
+        value: |nbt| nbt.xp_level,
+    },
+    NbtGauge {
+        // Score
+        name: "mc_score",
+        help: "current player score",
+        value: |nbt| nbt.score,
+    },
+    NbtGauge {
+        // Health
+        name: "mc_health",
+        help: "current player health",
+        value: |nbt| nbt.health,
+    },
+    NbtGauge {
+        // foodLevel
+        name: "mc_food_level",
+        help: "current player food level",
+        value: |nbt| nbt.food_level,
+    },
+];
 
-    Ok(())
+pub fn nbt_descs() -> Result<Vec<Desc>> {
+    NBT_GAUGES
+        .iter()
+        .map(|gauge| {
+            Desc::new(
+                String::from(gauge.name),
+                String::from(gauge.help),
+                vec![String::from("player")],
+                HashMap::new(),
+            )
+        })
+        .collect()
+}
+
+/// Families of the kept gauges with series of the kept players
+pub fn nbt_families(
+    snapshot: &Snapshot,
+    keep_family: &dyn Fn(&str) -> bool,
+    keep_player: &dyn Fn(&str) -> bool,
+) -> Vec<MetricFamily> {
+    NBT_GAUGES
+        .iter()
+        .filter(|gauge| keep_family(gauge.name))
+        .map(|gauge| {
+            let mut family = new_family(gauge.name, gauge.help, MetricType::GAUGE);
+
+            for player in snapshot.players.values().filter(|p| keep_player(&p.name)) {
+                let value = (gauge.value)(&player.nbt_stats);
+
+                family
+                    .mut_metric()
+                    .push(gauge_metric(&[("player", &player.name)], value));
+            }
+
+            family
+        })
+        .collect()
 }
@@ -1,16 +1,56 @@
-use crate::Result;
-use crate::{player::Player, prometheus_handler::stat_cache::STAT_CACHE, stats::STAT_CATEGORIES};
+use crate::prometheus_handler::family::{counter_metric, new_family};
+use crate::prometheus_handler::stat_cache::{get_category_metadata, Snapshot};
+use crate::stats::STAT_CATEGORIES;
+use prometheus::core::Desc;
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::Result;
+use std::collections::HashMap;
 
-pub async fn register_playerstats(player: &Player) -> Result<()> {
-    for category in STAT_CATEGORIES.iter() {
-        if let Some(stats) = player.stats.get_stat(category) {
-            for (key, value) in stats.iter() {
-                let value = value.as_f64().expect("Property value not a number");
+pub fn playerstats_descs() -> Result<Vec<Desc>> {
+    STAT_CATEGORIES
+        .iter()
+        .map(|category| {
+            let (name, help) = get_category_metadata(category);
 
-                STAT_CACHE.set_counter(player, category, key, value).await?;
+            Desc::new(
+                name,
+                help,
+                vec![String::from("player"), String::from("type")],
+                HashMap::new(),
+            )
+        })
+        .collect()
+}
+
+/// Families of the kept categories with series of the kept players
+pub fn playerstats_families(
+    snapshot: &Snapshot,
+    keep_family: &dyn Fn(&str) -> bool,
+    keep_player: &dyn Fn(&str) -> bool,
+) -> Vec<MetricFamily> {
+    STAT_CATEGORIES
+        .iter()
+        .map(|category| (category, get_category_metadata(category)))
+        .filter(|(_, (name, _))| keep_family(name))
+        .map(|(category, (name, help))| {
+            let mut family = new_family(&name, &help, MetricType::COUNTER);
+
+            for player in snapshot.players.values().filter(|p| keep_player(&p.name)) {
+                if let Some(stats) = player.stats.get_stat(category) {
+                    for (key, value) in stats.iter() {
+                        if let Some(value) = value.as_f64() {
+                            family.mut_metric().push(counter_metric(
+                                &[("player", &player.name), ("type", key)],
+                                value,
+                            ));
+                        } else {
+                            warn!("Property value of {} not a number for {}", key, player.name);
+                        }
+                    }
+                }
             }
-        }
-    }
 
-    Ok(())
+            family
+        })
+        .collect()
 }
@@ -1,130 +1,205 @@
+use crate::prometheus_handler::{
+    nbt::{nbt_descs, nbt_families},
+    playerstats::{playerstats_descs, playerstats_families},
+};
 use crate::Result;
-use crate::{player::Player, stats::StatCategory};
-use prometheus::{default_registry, Counter, Gauge, Registry};
-use std::{collections::HashMap, sync::Arc};
-use tokio::sync::Mutex;
+use crate::{
+    player::Player,
+    stats::{StatCategory, StatDiff},
+};
+use prometheus::{
+    core::{Collector, Desc},
+    proto::MetricFamily,
+    IntCounter, Registry,
+};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+use tokio::sync::broadcast;
+
+/// Changes buffered per subscriber before it starts lagging behind
+const CHANGE_CAPACITY: usize = 1024;
+
+/// Immutable view of all players known after a scrape, keyed by uuid
+#[derive(Debug, Default, Clone)]
+pub struct Snapshot {
+    pub players: HashMap<String, Arc<Player>>,
+    /// Time the counters of a player were started or last reset, keyed by uuid
+    pub created: HashMap<String, SystemTime>,
+    /// Time a player was last parsed, keyed by uuid
+    pub updated: HashMap<String, SystemTime>,
+}
 
-type CounterCache = HashMap<String, Counter>;
-type GaugeCache = HashMap<String, Gauge>;
+impl Snapshot {
+    /// Counter creation times keyed by player name, as used in the `player` label
+    pub fn created_by_player(&self) -> HashMap<&str, SystemTime> {
+        self.players
+            .iter()
+            .filter_map(|(uuid, player)| {
+                self.created
+                    .get(uuid)
+                    .map(|created| (player.name.as_str(), *created))
+            })
+            .collect()
+    }
 
-lazy_static! {
-    pub static ref STAT_CACHE: StatCache = StatCache::new();
-}
+    /// Stats that changed since `previous` of players known in both snapshots
+    pub fn changes_since(&self, previous: &Snapshot) -> Vec<StatChange> {
+        let mut changes = vec![];
 
-pub struct StatCache {
-    counter_cache: Arc<Mutex<CounterCache>>,
-    gauge_cache: Arc<Mutex<GaugeCache>>,
-    registry: &'static Registry,
-}
+        for (uuid, player) in self.players.iter() {
+            let before = match previous.players.get(uuid) {
+                // Players that were not parsed again are shared between snapshots
+                Some(before) if !Arc::ptr_eq(player, before) => before,
+                _ => continue,
+            };
 
-impl Default for StatCache {
-    fn default() -> Self {
-        Self {
-            counter_cache: Arc::new(Mutex::new(HashMap::new())),
-            gauge_cache: Arc::new(Mutex::new(HashMap::new())),
-            registry: default_registry(),
+            changes.extend(
+                player
+                    .stats
+                    .changes_since(&before.stats)
+                    .into_iter()
+                    .map(|diff| StatChange {
+                        player: player.name.clone(),
+                        diff,
+                    }),
+            );
         }
+
+        changes
     }
 }
 
+/// A stat of a known player that changed since the previous scrape
+#[derive(Debug, Clone, Serialize)]
+pub struct StatChange {
+    pub player: String,
+    #[serde(flatten)]
+    pub diff: StatDiff,
+}
+
+/// Collector rendering player metrics from the latest snapshot at gather time
+#[derive(Clone)]
+pub struct StatCache {
+    snapshot: Arc<RwLock<Arc<Snapshot>>>,
+    descs: Vec<Desc>,
+    counter_resets: IntCounter,
+    changes: broadcast::Sender<StatChange>,
+}
+
 impl StatCache {
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self> {
         trace!("Initialize stat cache");
-        StatCache::default()
-    }
 
-    pub async fn set_counter(
-        &self,
-        player: &Player,
-        category: &StatCategory,
-        category_type: &String,
-        value: f64,
-    ) -> Result<()> {
-        let counter = self.get_counter(player, category, category_type).await?;
-        counter.inc_by(value - counter.get());
+        let mut descs = playerstats_descs()?;
+        descs.extend(nbt_descs()?);
 
-        Ok(())
+        let counter_resets = IntCounter::new(
+            "mc_exporter_counter_resets_total",
+            "player stat counters that decreased and were reset",
+        )?;
+
+        let (changes, _) = broadcast::channel(CHANGE_CAPACITY);
+
+        Ok(Self {
+            snapshot: Arc::new(RwLock::new(Arc::new(Snapshot::default()))),
+            descs,
+            counter_resets,
+            changes,
+        })
     }
 
-    pub async fn set_gauge(
-        &self,
-        player: &Player,
-        category_type: &String,
-        category_help: &String,
-        value: f64,
-    ) -> Result<()> {
-        self.get_gauge(player, category_type, category_help)
-            .await?
-            .set(value);
+    /// Registers player metrics to `game` and metrics about the cache itself to `exporter`
+    pub fn register(&self, game: &Registry, exporter: &Registry) -> Result<()> {
+        game.register(Box::new(self.clone()))?;
+        exporter.register(Box::new(self.counter_resets.clone()))?;
 
         Ok(())
     }
 
-    async fn get_gauge(
-        &self,
-        player: &Player,
-        category_name: &String,
-        category_help: &String,
-    ) -> Result<Gauge> {
-        let id = gauge_id(player, category_name);
-        let mut gauge_cache = self.gauge_cache.lock().await;
-
-        if !gauge_cache.contains_key(&id) {
-            let labels: HashMap<&str, &String> = labels!(
-                "player" => &player.name,
-            );
+    /// Names of the families rendered from the player stats
+    pub fn family_names(&self) -> HashSet<String> {
+        self.descs.iter().map(|desc| desc.fq_name.clone()).collect()
+    }
 
-            let gauge = Gauge::with_opts(opts!(category_name, category_help, labels))?;
+    /// Merges freshly parsed players into a new snapshot and swaps it in.
+    /// Players that were not parsed again keep their previous values.
+    /// Stat changes of players already known are sent to all subscribers.
+    pub fn update(&self, players: Vec<Player>) {
+        let previous = self.snapshot();
+        let mut snapshot = Snapshot::clone(&previous);
+        let now = SystemTime::now();
+
+        for player in players {
+            let reset = match snapshot.players.get(&player.uuid) {
+                Some(previous) => player.stats.has_decreased_since(&previous.stats),
+                None => false,
+            };
 
-            self.registry.register(Box::new(gauge.clone()))?;
+            if reset {
+                warn!("Stats of {} decreased, resetting counters", player.name);
+                self.counter_resets.inc();
+                snapshot.created.insert(player.uuid.clone(), now);
+            } else if !snapshot.created.contains_key(&player.uuid) {
+                snapshot.created.insert(player.uuid.clone(), now);
+            }
+
+            snapshot.updated.insert(player.uuid.clone(), now);
+            snapshot
+                .players
+                .insert(player.uuid.clone(), Arc::new(player));
+        }
 
-            gauge_cache.insert(id.clone(), gauge);
+        let changes = snapshot.changes_since(&previous);
+        *self.snapshot.write().unwrap() = Arc::new(snapshot);
+
+        for change in changes {
+            // Sending only fails while nobody is subscribed
+            let _ = self.changes.send(change);
         }
+    }
 
-        Ok(gauge_cache.get(&id).unwrap().clone())
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.read().unwrap().clone()
     }
 
-    async fn get_counter(
+    /// Renders only the kept families and series of the kept players,
+    /// without building the others first
+    pub fn collect_matching(
         &self,
-        player: &Player,
-        category: &StatCategory,
-        category_type: &String,
-    ) -> Result<Counter> {
-        let id = counter_id(player, category, category_type);
-        let mut counter_cache = self.counter_cache.lock().await;
-
-        if !counter_cache.contains_key(&id) {
-            let labels: HashMap<&str, &String> = labels!(
-                "player" => &player.name,
-                "type" => category_type,
-            );
-
-            let (category_name, category_help) = get_category_metadata(category);
+        keep_family: &dyn Fn(&str) -> bool,
+        keep_player: &dyn Fn(&str) -> bool,
+    ) -> Vec<MetricFamily> {
+        let snapshot = self.snapshot();
 
-            let counter = Counter::with_opts(opts!(
-                &String::from(&category_name),
-                &String::from(&category_help),
-                labels
-            ))?;
+        let mut families = playerstats_families(&snapshot, keep_family, keep_player);
+        families.extend(nbt_families(&snapshot, keep_family, keep_player));
+        // Like a registry gather, families without series are left out
+        families.retain(|family| !family.get_metric().is_empty());
 
-            self.registry.register(Box::new(counter.clone()))?;
-
-            counter_cache.insert(id.clone(), counter);
-        }
+        families
+    }
 
-        Ok(counter_cache.get(&id).unwrap().clone())
+    /// Receives stat changes of all following updates
+    pub fn subscribe(&self) -> broadcast::Receiver<StatChange> {
+        self.changes.subscribe()
     }
 }
 
-fn counter_id(player: &Player, category: &StatCategory, category_type: &String) -> String {
-    format!("{}_{}_{}", &player.uuid, category, category_type)
-}
+impl Collector for StatCache {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
 
-fn gauge_id(player: &Player, category_name: &String) -> String {
-    format!("{}_{}", &player.uuid, category_name)
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.collect_matching(&|_| true, &|_| true)
+    }
 }
 
-fn get_category_metadata(category: &StatCategory) -> (String, String) {
+pub fn get_category_metadata(category: &StatCategory) -> (String, String) {
     let stat_str = {
         let s = &category.to_string();
         s[10..].to_string()
@@ -139,51 +214,14 @@ fn get_category_metadata(category: &StatCategory) -> (String, String) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::mock_player;
-
-    macro_rules! mock_gauge_cache {
-        () => {
-            mock_gauge_cache!(
-                &mock_player!(999999),
-                &String::from("some_gauge_name"),
-                1337.0
-            )
-        };
-        ($player:expr, $category_name:expr, $value:expr) => {{
-            let id = gauge_id($player, $category_name);
-            let gauge = Gauge::with_opts(opts!(
-                $category_name.clone(),
-                format!("help: {}", $category_name)
-            ))
-            .unwrap();
-            gauge.set($value);
-
-            let mut c = HashMap::new();
-            c.insert(id, gauge);
-
-            Arc::new(Mutex::new(c))
-        }};
-    }
-
-    macro_rules! mock_counter_cache {
-        () => {
-            mock_counter_cache!(
-                &mock_player!(999999),
-                &StatCategory::KilledBy,
-                &String::from("some_type"),
-                10.0
-            )
-        };
-        ($player:expr, $stat_category:expr, $category_type:expr, $value:expr) => {{
-            let id = counter_id($player, $stat_category, $category_type);
-            let counter = Counter::with_opts(opts!("Testi", "Help")).unwrap();
-            counter.inc_by($value);
-
-            let mut c = HashMap::new();
-            c.insert(id, counter);
-
-            Arc::new(Mutex::new(c))
-        }};
+    use crate::{mock_custom_stats, mock_player};
+    use std::time::UNIX_EPOCH;
+
+    fn find_family<'a>(families: &'a [MetricFamily], name: &str) -> &'a MetricFamily {
+        families
+            .iter()
+            .find(|family| family.get_name() == name)
+            .unwrap()
     }
 
     mod get_category_metadata {
@@ -200,117 +238,266 @@ mod tests {
         }
     }
 
-    mod set_gauge {
+    mod update {
         use super::*;
 
-        #[tokio::test]
-        async fn should_update_existing_stat() {
-            let player = mock_player!(1);
-            let category_name = String::from("gauge_name");
-            let category_help = String::from("minimal amount of xp");
-            let value = 2.0;
-
-            let cache = StatCache {
-                gauge_cache: mock_gauge_cache!(&player, &category_name, value),
-                counter_cache: mock_counter_cache!(),
-                registry: default_registry(),
-            };
+        #[test]
+        fn should_insert_new_player() {
+            let cache = StatCache::new().unwrap();
 
-            let value = 5.0;
-            cache
-                .set_gauge(&player, &category_name, &category_help, value)
-                .await
-                .unwrap();
+            cache.update(vec![mock_player!(1)]);
 
-            let actual = cache
-                .get_gauge(&player, &category_name, &category_help)
-                .await
-                .unwrap();
+            let actual = cache.snapshot();
 
-            assert_eq!(actual.get(), value);
+            assert_eq!(actual.players.len(), 1);
+            assert!(actual.players.contains_key("1"));
         }
 
-        #[tokio::test]
-        async fn should_insert_new_stat() {
-            let player = mock_player!(1);
-            let category_name = String::from("gauge_name");
-            let category_help = String::from("minimal amount of xp");
-            let value = 2.0;
-
-            let cache = StatCache {
-                gauge_cache: mock_gauge_cache!(),
-                counter_cache: mock_counter_cache!(),
-                registry: default_registry(),
-            };
+        #[test]
+        fn should_update_existing_player() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![mock_player!(1)]);
 
-            cache
-                .set_gauge(&player, &category_name, &category_help, value)
-                .await
-                .unwrap();
+            let mut player = mock_player!(1);
+            player.nbt_stats.health = 5.0;
+            cache.update(vec![player]);
 
-            let actual = cache
-                .get_gauge(&player, &category_name, &category_help)
-                .await
-                .unwrap();
+            let actual = cache.snapshot();
 
-            assert_eq!(actual.get(), value);
+            assert_eq!(actual.players.len(), 1);
+            assert_eq!(actual.players["1"].nbt_stats.health, 5.0);
+        }
+
+        #[test]
+        fn should_keep_players_not_updated() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![mock_player!(1), mock_player!(2)]);
+
+            cache.update(vec![mock_player!(2)]);
+
+            let actual = cache.snapshot();
+
+            assert_eq!(actual.players.len(), 2);
+        }
+
+        #[test]
+        fn should_not_change_previous_snapshot() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![mock_player!(1)]);
+            let previous = cache.snapshot();
+
+            cache.update(vec![mock_player!(2)]);
+
+            assert_eq!(previous.players.len(), 1);
         }
     }
 
-    mod set_counter {
+    mod counter_reset {
         use super::*;
+        use crate::stats::Stats;
 
-        #[tokio::test]
-        async fn should_update_existing_stat() {
-            let player = mock_player!(1);
-            let category = StatCategory::Crafted;
-            let category_type = String::from("minecraft:test1");
-            let value = 2.0;
-
-            let cache = StatCache {
-                gauge_cache: mock_gauge_cache!(),
-                counter_cache: mock_counter_cache!(&player, &category, &category_type, value),
-                registry: default_registry(),
-            };
+        fn player_with_stat(id: u32, value: u32) -> Player {
+            let mut player = mock_player!(id);
+            player.stats = Stats::from(format!(
+                "{{ \"stats\": {{ \"minecraft:mined\": {{ \"minecraft:stone\": {} }} }} }}",
+                value
+            ))
+            .unwrap();
 
-            let value = 5.0;
-            cache
-                .set_counter(&player, &category, &category_type, value)
-                .await
-                .unwrap();
+            player
+        }
+
+        #[test]
+        fn should_not_reset_on_increase() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![player_with_stat(1, 5)]);
+            let created = cache.snapshot().created["1"];
+
+            cache.update(vec![player_with_stat(1, 7)]);
+
+            assert_eq!(cache.counter_resets.get(), 0);
+            assert_eq!(cache.snapshot().created["1"], created);
+        }
+
+        #[test]
+        fn should_reset_on_decrease() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![player_with_stat(1, 5)]);
+            let mut snapshot = Snapshot::clone(&cache.snapshot());
+            snapshot.created.insert(String::from("1"), UNIX_EPOCH);
+            *cache.snapshot.write().unwrap() = Arc::new(snapshot);
+
+            cache.update(vec![player_with_stat(1, 3)]);
+
+            assert_eq!(cache.counter_resets.get(), 1);
+            assert!(cache.snapshot().created["1"] > UNIX_EPOCH);
+        }
+
+        #[test]
+        fn should_reset_on_removed_stat() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![player_with_stat(1, 5)]);
+
+            cache.update(vec![mock_player!(1)]);
+
+            assert_eq!(cache.counter_resets.get(), 1);
+        }
+
+        #[test]
+        fn should_render_lower_value_after_reset() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![player_with_stat(1, 5)]);
+            cache.update(vec![player_with_stat(1, 3)]);
 
-            let actual = cache
-                .get_counter(&player, &category, &category_type)
-                .await
-                .unwrap();
+            let families = cache.collect();
+            let actual = find_family(&families, "mc_mined");
 
-            assert_eq!(actual.get(), value);
+            assert_eq!(actual.get_metric()[0].get_counter().get_value(), 3.0);
+        }
+    }
+
+    mod subscribe {
+        use super::*;
+        use crate::stats::Stats;
+
+        fn player_with_stats(id: u32, stats: &str) -> Player {
+            let mut player = mock_player!(id);
+            player.stats = Stats::from(format!(
+                "{{ \"stats\": {{ \"minecraft:mined\": {} }} }}",
+                stats
+            ))
+            .unwrap();
+
+            player
         }
 
         #[tokio::test]
-        async fn should_insert_new_stat() {
-            let cache = StatCache {
-                gauge_cache: mock_gauge_cache!(),
-                counter_cache: mock_counter_cache!(),
-                registry: default_registry(),
+        async fn should_send_changed_stats() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![player_with_stats(1, "{ \"minecraft:stone\": 5 }")]);
+            let mut changes = cache.subscribe();
+
+            cache.update(vec![player_with_stats(
+                1,
+                "{ \"minecraft:stone\": 7, \"minecraft:diamond_ore\": 1 }",
+            )]);
+
+            let mut actual = [changes.recv().await.unwrap(), changes.recv().await.unwrap()];
+            actual.sort_by(|a, b| a.diff.key.cmp(&b.diff.key));
+
+            assert_eq!(actual[0].player, "name-1");
+            assert_eq!(actual[0].diff.key, "minecraft:diamond_ore");
+            assert_eq!(actual[0].diff.old, None);
+            assert_eq!(actual[1].diff.category, StatCategory::Mined);
+            assert_eq!(actual[1].diff.old, Some(5.0));
+            assert_eq!(actual[1].diff.new, 7.0);
+        }
+
+        #[tokio::test]
+        async fn should_not_send_unchanged_or_new_players() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![player_with_stats(1, "{ \"minecraft:stone\": 5 }")]);
+            let mut changes = cache.subscribe();
+
+            cache.update(vec![
+                player_with_stats(1, "{ \"minecraft:stone\": 5 }"),
+                player_with_stats(2, "{ \"minecraft:stone\": 5 }"),
+            ]);
+
+            assert!(changes.try_recv().is_err());
+        }
+
+        #[test]
+        fn should_serialize_flat() {
+            let change = StatChange {
+                player: String::from("name-1"),
+                diff: StatDiff {
+                    category: StatCategory::Mined,
+                    key: String::from("minecraft:stone"),
+                    old: None,
+                    new: 1.0,
+                },
             };
 
-            let player = &mock_player!(1);
-            let category = &StatCategory::Crafted;
-            let category_type = &String::from("minecraft:test2");
-            let value = 2.0;
+            let actual = serde_json::to_string(&change).unwrap();
+            let expected = "{\"player\":\"name-1\",\"category\":\"minecraft:mined\",\"key\":\"minecraft:stone\",\"old\":null,\"new\":1.0}";
+
+            assert_eq!(actual, expected);
+        }
+    }
+
+    mod register {
+        use super::*;
+
+        #[test]
+        fn should_separate_game_and_exporter_metrics() {
+            let cache = StatCache::new().unwrap();
+            let game = Registry::new();
+            let exporter = Registry::new();
+            cache.register(&game, &exporter).unwrap();
+            cache.update(vec![mock_player!(1)]);
+
+            let game_families = game.gather();
+            let exporter_families = exporter.gather();
+
+            assert!(game_families
+                .iter()
+                .all(|family| !family.get_name().starts_with("mc_exporter_")));
+            assert_eq!(
+                exporter_families[0].get_name(),
+                "mc_exporter_counter_resets_total"
+            );
+        }
+    }
+
+    mod collect {
+        use super::*;
+
+        #[test]
+        fn should_render_gauges_per_player() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![mock_player!(1), mock_player!(2)]);
+
+            let families = cache.collect();
+            let actual = find_family(&families, "mc_health");
+
+            assert_eq!(actual.get_metric().len(), 2);
+            assert_eq!(actual.get_metric()[0].get_gauge().get_value(), 10.0);
+        }
 
-            cache
-                .set_counter(player, category, category_type, value)
-                .await
-                .unwrap();
+        #[test]
+        fn should_render_counters_with_type_label() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![mock_player!(1, mock_custom_stats!())]);
 
-            let actual = cache
-                .get_counter(player, category, category_type)
-                .await
-                .unwrap();
+            let families = cache.collect();
+            let actual = find_family(&families, "mc_custom");
+            let metric = &actual.get_metric()[0];
 
-            assert_eq!(actual.get(), value);
+            assert_eq!(metric.get_counter().get_value(), 42.0);
+            assert_eq!(metric.get_label()[0].get_value(), "name-1");
+            assert_eq!(metric.get_label()[1].get_value(), "minecraft:testo");
+        }
+    }
+
+    mod collect_matching {
+        use super::*;
+
+        #[test]
+        fn should_only_render_kept_families_and_players() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![mock_player!(1), mock_player!(2)]);
+
+            let actual = cache.collect_matching(&|family| family == "mc_health", &|player| {
+                player == "name-2"
+            });
+
+            assert_eq!(actual.len(), 1);
+            assert_eq!(actual[0].get_metric().len(), 1);
+            assert_eq!(
+                actual[0].get_metric()[0].get_label()[0].get_value(),
+                "name-2"
+            );
         }
     }
 }
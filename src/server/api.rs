@@ -0,0 +1,221 @@
+use crate::player::Player;
+use crate::prometheus_handler::Snapshot;
+use crate::stats::{NbtStats, StatCategory, Stats};
+use hyper::{header::CONTENT_TYPE, Body, Response, StatusCode};
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use std::time::UNIX_EPOCH;
+
+type ResponseResult = std::result::Result<Response<Body>, hyper::http::Error>;
+
+#[derive(Debug, Serialize)]
+struct PlayerSummary<'a> {
+    uuid: &'a str,
+    name: &'a str,
+    /// Seconds since epoch the player files were last parsed
+    last_update: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct PlayerDetails<'a> {
+    #[serde(flatten)]
+    summary: PlayerSummary<'a>,
+    #[serde(flatten)]
+    stats: &'a Stats,
+    nbt_stats: &'a NbtStats,
+}
+
+#[derive(Debug, Serialize)]
+struct StatValue<'a> {
+    uuid: &'a str,
+    name: &'a str,
+    value: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiError<'a> {
+    error: &'a str,
+}
+
+/// Answers read-only JSON requests below `/api/` from the latest snapshot
+pub fn api_response(path: &str, snapshot: &Snapshot) -> ResponseResult {
+    let segments: Vec<String> = path
+        .trim_start_matches("/api/")
+        .trim_end_matches('/')
+        .split('/')
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+
+    match segments.as_slice() {
+        ["players"] => {
+            let players: Vec<_> = sorted_players(snapshot)
+                .into_iter()
+                .map(|player| summary(snapshot, player))
+                .collect();
+
+            json_response(StatusCode::OK, &players)
+        }
+        ["players", id] => match find_player(snapshot, id) {
+            Some(player) => json_response(
+                StatusCode::OK,
+                &PlayerDetails {
+                    summary: summary(snapshot, player),
+                    stats: &player.stats,
+                    nbt_stats: &player.nbt_stats,
+                },
+            ),
+            None => error_response(StatusCode::NOT_FOUND, "Player not found"),
+        },
+        ["stats", category, key] => match StatCategory::from_name(category) {
+            Some(category) => {
+                let key = if key.contains(':') {
+                    String::from(*key)
+                } else {
+                    format!("minecraft:{}", key)
+                };
+
+                let values: Vec<_> = sorted_players(snapshot)
+                    .into_iter()
+                    .filter_map(|player| {
+                        let value = player.stats.get_stat(&category)?.get(&key)?.as_f64()?;
+
+                        Some(StatValue {
+                            uuid: &player.uuid,
+                            name: &player.name,
+                            value,
+                        })
+                    })
+                    .collect();
+
+                json_response(StatusCode::OK, &values)
+            }
+            None => error_response(StatusCode::NOT_FOUND, "Unknown stat category"),
+        },
+        _ => error_response(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+fn sorted_players(snapshot: &Snapshot) -> Vec<&Player> {
+    let mut players: Vec<&Player> = snapshot.players.values().map(|p| p.as_ref()).collect();
+    players.sort_by(|a, b| a.name.cmp(&b.name));
+
+    players
+}
+
+fn find_player<'a>(snapshot: &'a Snapshot, id: &str) -> Option<&'a Player> {
+    snapshot
+        .players
+        .get(id)
+        .or_else(|| {
+            snapshot
+                .players
+                .values()
+                .find(|player| player.name.eq_ignore_ascii_case(id))
+        })
+        .map(|player| player.as_ref())
+}
+
+fn summary<'a>(snapshot: &Snapshot, player: &'a Player) -> PlayerSummary<'a> {
+    let last_update = snapshot
+        .updated
+        .get(&player.uuid)
+        .and_then(|updated| updated.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs());
+
+    PlayerSummary {
+        uuid: &player.uuid,
+        name: &player.name,
+        last_update,
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, value: &T) -> ResponseResult {
+    let body = serde_json::to_vec(value).unwrap_or_default();
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+}
+
+fn error_response(status: StatusCode, error: &str) -> ResponseResult {
+    json_response(status, &ApiError { error })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prometheus_handler::StatCache;
+    use crate::{mock_custom_stats, mock_player};
+    use serde_json::Value;
+
+    fn mock_snapshot() -> std::sync::Arc<Snapshot> {
+        let cache = StatCache::new().unwrap();
+        cache.update(vec![
+            mock_player!(2, mock_custom_stats!()),
+            mock_player!(1, mock_custom_stats!()),
+        ]);
+
+        cache.snapshot()
+    }
+
+    async fn json_of(path: &str) -> (StatusCode, Value) {
+        let response = api_response(path, &mock_snapshot()).unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    mod api_response {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_list_players_by_name() {
+            let (status, actual) = json_of("/api/players").await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(actual[0]["name"], "name-1");
+            assert_eq!(actual[1]["uuid"], "2");
+            assert!(actual[0]["last_update"].is_u64());
+        }
+
+        #[tokio::test]
+        async fn should_find_player_by_uuid_or_name() {
+            let (_, by_uuid) = json_of("/api/players/1").await;
+            let (_, by_name) = json_of("/api/players/NAME-1").await;
+
+            assert_eq!(by_uuid["name"], "name-1");
+            assert_eq!(by_uuid["nbt_stats"]["health"], 10.0);
+            assert_eq!(by_uuid["stats"]["minecraft:custom"]["minecraft:testo"], 42);
+            assert_eq!(by_name["uuid"], "1");
+        }
+
+        #[tokio::test]
+        async fn should_return_not_found_for_unknown_player() {
+            let (status, actual) = json_of("/api/players/nobody").await;
+
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(actual["error"], "Player not found");
+        }
+
+        #[tokio::test]
+        async fn should_list_stat_values_per_player() {
+            let (status, actual) = json_of("/api/stats/custom/testo").await;
+            let (_, prefixed) = json_of("/api/stats/minecraft:custom/minecraft%3Atesto").await;
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(actual.as_array().unwrap().len(), 2);
+            assert_eq!(actual[0]["value"], 42.0);
+            assert_eq!(actual, prefixed);
+        }
+
+        #[tokio::test]
+        async fn should_return_not_found_for_unknown_category() {
+            let (status, _) = json_of("/api/stats/jumped/testo").await;
+
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use hyper::{header::AUTHORIZATION, Body, HeaderMap, Request};
+use std::collections::HashMap;
+use tokio::task;
+
+/// Checks the basic auth credentials of a request against bcrypt hashed passwords.
+/// Every request is allowed if no users are configured.
+pub async fn is_authorized(users: &HashMap<String, String>, req: &Request<Body>) -> bool {
+    if users.is_empty() {
+        return true;
+    }
+
+    let (user, password) = match basic_auth_credentials(req.headers()) {
+        Some(credentials) => credentials,
+        None => return false,
+    };
+
+    let hash = match users.get(&user) {
+        Some(hash) => hash.clone(),
+        None => return false,
+    };
+
+    // bcrypt is slow on purpose, keep it away from the async runtime
+    match task::spawn_blocking(move || bcrypt::verify(password, &hash)).await {
+        Ok(Ok(valid)) => valid,
+        Ok(Err(e)) => {
+            error!("Could not verify password of {}: {}", user, e);
+            false
+        }
+        Err(e) => {
+            error!("Password verification failed: {}", e);
+            false
+        }
+    }
+}
+
+fn basic_auth_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+
+    if !value.starts_with("Basic ") {
+        return None;
+    }
+
+    let decoded = base64::decode(value[6..].trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+
+    Some((String::from(parts.next()?), String::from(parts.next()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_with_auth(credentials: &str) -> Request<Body> {
+        Request::builder()
+            .header(
+                AUTHORIZATION,
+                format!("Basic {}", base64::encode(credentials)),
+            )
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn mock_users() -> HashMap<String, String> {
+        let mut users = HashMap::new();
+        users.insert(String::from("alice"), bcrypt::hash("secret", 4).unwrap());
+
+        users
+    }
+
+    mod is_authorized {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_allow_everyone_without_users() {
+            let req = Request::new(Body::empty());
+
+            assert!(is_authorized(&HashMap::new(), &req).await);
+        }
+
+        #[tokio::test]
+        async fn should_allow_valid_credentials() {
+            let req = request_with_auth("alice:secret");
+
+            assert!(is_authorized(&mock_users(), &req).await);
+        }
+
+        #[tokio::test]
+        async fn should_reject_wrong_password() {
+            let req = request_with_auth("alice:wrong");
+
+            assert!(!is_authorized(&mock_users(), &req).await);
+        }
+
+        #[tokio::test]
+        async fn should_reject_unknown_user() {
+            let req = request_with_auth("bob:secret");
+
+            assert!(!is_authorized(&mock_users(), &req).await);
+        }
+
+        #[tokio::test]
+        async fn should_reject_missing_header() {
+            let req = Request::new(Body::empty());
+
+            assert!(!is_authorized(&mock_users(), &req).await);
+        }
+    }
+}
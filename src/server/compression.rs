@@ -0,0 +1,175 @@
+use crate::Result;
+use flate2::write::GzEncoder;
+use hyper::{header::ACCEPT_ENCODING, HeaderMap};
+use std::io::Write;
+
+/// Content encodings a client can ask for with the `Accept-Encoding` header
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    Identity,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Picks the supported encoding with the highest quality, preferring zstd over gzip on ties
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let accept = match headers.get(ACCEPT_ENCODING).and_then(|a| a.to_str().ok()) {
+            Some(accept) => accept,
+            None => return Compression::Identity,
+        };
+
+        let mut best: Option<(Compression, f32)> = None;
+
+        for coding in accept.split(',') {
+            let mut parts = coding.split(';').map(|p| p.trim());
+            let name = parts.next().unwrap_or("").to_lowercase();
+            let quality = parts
+                .filter(|p| p.starts_with("q="))
+                .map(|p| p[2..].parse().unwrap_or(0.0))
+                .next()
+                .unwrap_or(1.0);
+
+            let compression = match name.as_str() {
+                "zstd" => Compression::Zstd,
+                "gzip" | "*" => Compression::Gzip,
+                _ => continue,
+            };
+
+            let better = match best {
+                None => true,
+                Some((previous, q)) => {
+                    quality > q
+                        || (quality == q
+                            && compression == Compression::Zstd
+                            && previous != compression)
+                }
+            };
+
+            if quality > 0.0 && better {
+                best = Some((compression, quality));
+            }
+        }
+
+        best.map(|(compression, _)| compression)
+            .unwrap_or(Compression::Identity)
+    }
+
+    pub fn content_encoding(self) -> Option<&'static str> {
+        match self {
+            Compression::Identity => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Hands `write` a writer compressing into `writer` and finishes the compression afterwards
+    pub fn compress<W, F>(self, writer: W, write: F) -> Result<()>
+    where
+        W: Write,
+        F: FnOnce(&mut dyn Write) -> Result<()>,
+    {
+        match self {
+            Compression::Identity => {
+                let mut writer = writer;
+                write(&mut writer)?;
+                writer.flush()?;
+            }
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(writer, flate2::Compression::default());
+                write(&mut encoder)?;
+                encoder.finish()?.flush()?;
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, 0)?;
+                write(&mut encoder)?;
+                encoder.finish()?.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use hyper::header::HeaderValue;
+    use std::io::Read;
+
+    fn compression_for(accept: &'static str) -> Compression {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(accept));
+
+        Compression::from_headers(&headers)
+    }
+
+    mod from_headers {
+        use super::*;
+
+        #[test]
+        fn should_default_to_identity() {
+            assert_eq!(
+                Compression::from_headers(&HeaderMap::new()),
+                Compression::Identity
+            );
+            assert_eq!(compression_for("br, deflate"), Compression::Identity);
+        }
+
+        #[test]
+        fn should_select_gzip() {
+            assert_eq!(compression_for("gzip, deflate, br"), Compression::Gzip);
+        }
+
+        #[test]
+        fn should_prefer_zstd_on_equal_quality() {
+            assert_eq!(compression_for("gzip, zstd"), Compression::Zstd);
+        }
+
+        #[test]
+        fn should_honour_quality() {
+            assert_eq!(compression_for("zstd;q=0.5, gzip"), Compression::Gzip);
+            assert_eq!(compression_for("gzip;q=0"), Compression::Identity);
+        }
+    }
+
+    mod compress {
+        use super::*;
+
+        #[test]
+        fn should_gzip_written_data() {
+            let mut buffer = vec![];
+
+            Compression::Gzip
+                .compress(&mut buffer, |w| {
+                    w.write_all(b"mc_health 20\n")?;
+                    Ok(())
+                })
+                .unwrap();
+
+            let mut actual = String::new();
+            GzDecoder::new(&buffer[..])
+                .read_to_string(&mut actual)
+                .unwrap();
+
+            assert_eq!(actual, "mc_health 20\n");
+        }
+
+        #[test]
+        fn should_zstd_written_data() {
+            let mut buffer = vec![];
+
+            Compression::Zstd
+                .compress(&mut buffer, |w| {
+                    w.write_all(b"mc_health 20\n")?;
+                    Ok(())
+                })
+                .unwrap();
+
+            let actual = zstd::decode_all(&buffer[..]).unwrap();
+
+            assert_eq!(actual, b"mc_health 20\n");
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::prometheus_handler::retain_players;
+use prometheus::proto::MetricFamily;
+use std::collections::HashSet;
+use url::form_urlencoded;
+
+/// Restricts the encoded metrics by `collect[]=<family>` and `player=<name>` query parameters
+#[derive(Debug, Default)]
+pub struct MetricFilter {
+    families: HashSet<String>,
+    players: HashSet<String>,
+}
+
+impl MetricFilter {
+    pub fn from_query(query: Option<&str>) -> Self {
+        let mut filter = MetricFilter::default();
+
+        for (key, value) in form_urlencoded::parse(query.unwrap_or("").as_bytes()) {
+            match key.as_ref() {
+                "collect[]" => {
+                    filter.families.insert(value.into_owned());
+                }
+                "player" => {
+                    filter.players.insert(value.into_owned());
+                }
+                _ => {}
+            }
+        }
+
+        filter
+    }
+
+    pub fn keeps_family(&self, name: &str) -> bool {
+        self.families.is_empty() || self.families.contains(name)
+    }
+
+    pub fn keeps_player(&self, name: &str) -> bool {
+        self.players.is_empty() || self.players.contains(name)
+    }
+
+    /// Drops families and player series not asked for.
+    /// Series without a `player` label are kept when filtering by player.
+    pub fn apply(&self, families: Vec<MetricFamily>) -> Vec<MetricFamily> {
+        families
+            .into_iter()
+            .filter(|family| self.keeps_family(family.get_name()))
+            .filter_map(|family| {
+                if self.players.is_empty() {
+                    return Some(family);
+                }
+
+                retain_players(family, &|player| self.keeps_player(player))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{CounterVec, Gauge, Opts, Registry};
+
+    fn mock_families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+
+        let mined = CounterVec::new(Opts::new("mc_mined", "mined"), &["player", "type"]).unwrap();
+        mined.with_label_values(&["alice", "minecraft:stone"]).inc();
+        mined.with_label_values(&["bob", "minecraft:dirt"]).inc();
+        registry.register(Box::new(mined)).unwrap();
+
+        let health = Gauge::new("mc_up", "up").unwrap();
+        registry.register(Box::new(health)).unwrap();
+
+        registry.gather()
+    }
+
+    fn names(families: &[MetricFamily]) -> Vec<&str> {
+        families.iter().map(|family| family.get_name()).collect()
+    }
+
+    mod from_query {
+        use super::*;
+
+        #[test]
+        fn should_decode_parameters() {
+            let actual = MetricFilter::from_query(Some(
+                "collect%5B%5D=mc_health&collect[]=mc_food_level&player=Alice&other=1",
+            ));
+
+            assert!(actual.families.contains("mc_health"));
+            assert!(actual.families.contains("mc_food_level"));
+            assert!(actual.players.contains("Alice"));
+            assert_eq!(actual.families.len(), 2);
+        }
+    }
+
+    mod apply {
+        use super::*;
+
+        #[test]
+        fn should_keep_everything_without_parameters() {
+            let actual = MetricFilter::from_query(None).apply(mock_families());
+
+            assert_eq!(names(&actual), vec!["mc_mined", "mc_up"]);
+        }
+
+        #[test]
+        fn should_keep_collected_families() {
+            let actual = MetricFilter::from_query(Some("collect[]=mc_up")).apply(mock_families());
+
+            assert_eq!(names(&actual), vec!["mc_up"]);
+        }
+
+        #[test]
+        fn should_keep_series_of_player() {
+            let actual = MetricFilter::from_query(Some("player=bob")).apply(mock_families());
+            let mined = &actual[0];
+
+            assert_eq!(names(&actual), vec!["mc_mined", "mc_up"]);
+            assert_eq!(mined.get_metric().len(), 1);
+            assert_eq!(mined.get_metric()[0].get_label()[0].get_value(), "bob");
+        }
+
+        #[test]
+        fn should_drop_families_without_matching_player() {
+            let actual = MetricFilter::from_query(Some("collect[]=mc_mined&player=carol"))
+                .apply(mock_families());
+
+            assert!(actual.is_empty());
+        }
+    }
+}
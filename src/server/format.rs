@@ -0,0 +1,152 @@
+use crate::server::open_metrics::{OpenMetricsEncoder, OPEN_METRICS_FORMAT};
+use crate::Result;
+use hyper::{header::ACCEPT, HeaderMap};
+use prometheus::{proto::MetricFamily, Encoder, ProtobufEncoder, TextEncoder};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    time::SystemTime,
+};
+
+/// Exposition formats a scraper can ask for with the `Accept` header
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Text,
+    OpenMetrics,
+    Protobuf,
+}
+
+impl Format {
+    /// Picks the supported format with the highest quality, the classic text format by default
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let accept = match headers.get(ACCEPT).and_then(|a| a.to_str().ok()) {
+            Some(accept) => accept,
+            None => return Format::Text,
+        };
+
+        let mut best: Option<(Format, f32)> = None;
+
+        for media_range in accept.split(',') {
+            let mut parts = media_range.split(';').map(|p| p.trim());
+            let media_type = parts.next().unwrap_or("");
+            let mut quality = 1.0;
+            let mut params = HashMap::new();
+
+            for param in parts {
+                let mut kv = param.splitn(2, '=');
+                let key = kv.next().unwrap_or("").trim();
+                let value = kv.next().unwrap_or("").trim();
+
+                if key == "q" {
+                    quality = value.parse().unwrap_or(0.0);
+                } else {
+                    params.insert(key, value);
+                }
+            }
+
+            let format = match media_type {
+                "application/openmetrics-text" => Format::OpenMetrics,
+                "application/vnd.google.protobuf"
+                    if params.get("proto") == Some(&"io.prometheus.client.MetricFamily")
+                        && params.get("encoding") == Some(&"delimited") =>
+                {
+                    Format::Protobuf
+                }
+                "text/plain" | "*/*" => Format::Text,
+                _ => continue,
+            };
+
+            if quality > 0.0 && best.map_or(true, |(_, q)| quality > q) {
+                best = Some((format, quality));
+            }
+        }
+
+        best.map(|(format, _)| format).unwrap_or(Format::Text)
+    }
+
+    pub fn content_type(self) -> String {
+        match self {
+            Format::Text => TextEncoder::new().format_type().to_string(),
+            Format::OpenMetrics => OPEN_METRICS_FORMAT.to_string(),
+            Format::Protobuf => ProtobufEncoder::new().format_type().to_string(),
+        }
+    }
+
+    pub fn encode<W: Write>(
+        self,
+        families: &[MetricFamily],
+        created_by_player: HashMap<&str, SystemTime>,
+        player_families: &HashSet<String>,
+        default_created: SystemTime,
+        writer: &mut W,
+    ) -> Result<()> {
+        match self {
+            Format::Text => TextEncoder::new().encode(families, writer)?,
+            Format::OpenMetrics => {
+                OpenMetricsEncoder::new(created_by_player, player_families, default_created)
+                    .encode(families, writer)?
+            }
+            Format::Protobuf => ProtobufEncoder::new().encode(families, writer)?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn format_for(accept: &'static str) -> Format {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(accept));
+
+        Format::from_headers(&headers)
+    }
+
+    mod from_headers {
+        use super::*;
+
+        #[test]
+        fn should_default_to_text() {
+            assert_eq!(Format::from_headers(&HeaderMap::new()), Format::Text);
+            assert_eq!(format_for("*/*"), Format::Text);
+            assert_eq!(format_for("application/json"), Format::Text);
+        }
+
+        #[test]
+        fn should_prefer_open_metrics_of_prometheus() {
+            let actual = format_for(
+                "application/openmetrics-text;version=1.0.0,application/openmetrics-text;version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1",
+            );
+
+            assert_eq!(actual, Format::OpenMetrics);
+        }
+
+        #[test]
+        fn should_honour_quality() {
+            let actual = format_for("application/openmetrics-text;q=0.2,text/plain;q=0.9");
+
+            assert_eq!(actual, Format::Text);
+        }
+
+        #[test]
+        fn should_select_delimited_protobuf() {
+            let actual = format_for(
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=delimited;q=0.7,text/plain;version=0.0.4;q=0.3",
+            );
+
+            assert_eq!(actual, Format::Protobuf);
+        }
+
+        #[test]
+        fn should_ignore_other_protobuf_encodings() {
+            let actual = format_for(
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=text",
+            );
+
+            assert_eq!(actual, Format::Text);
+        }
+    }
+}
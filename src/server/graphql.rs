@@ -0,0 +1,460 @@
+use crate::player::Player;
+use crate::prometheus_handler::Snapshot;
+use crate::stats::{NbtStats, StatCategory};
+use hyper::{
+    body::HttpBody,
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    Body, Method, Request, Response, StatusCode,
+};
+use juniper::{http::GraphQLRequest, EmptyMutation, InputValue, RootNode};
+use std::{cmp::Ordering, sync::Arc, time::UNIX_EPOCH};
+use url::form_urlencoded;
+
+type ResponseResult = std::result::Result<Response<Body>, hyper::http::Error>;
+
+/// Queries are small, larger bodies are rejected before they are read completely
+const MAX_BODY_SIZE: usize = 64 * 1024;
+type Schema = RootNode<'static, Query, EmptyMutation<Context>>;
+
+pub struct Context {
+    snapshot: Arc<Snapshot>,
+}
+
+impl juniper::Context for Context {}
+
+#[derive(juniper::GraphQLEnum, Debug, Copy, Clone, PartialEq)]
+pub enum PlayerSort {
+    Name,
+    Health,
+    FoodLevel,
+    XpLevel,
+    Score,
+}
+
+#[derive(juniper::GraphQLEnum, Debug, Copy, Clone, PartialEq)]
+pub enum StatSort {
+    Key,
+    Value,
+}
+
+#[derive(juniper::GraphQLEnum, Debug, Copy, Clone, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(juniper::GraphQLObject, Debug)]
+pub struct Stat {
+    key: String,
+    value: f64,
+}
+
+pub struct Query;
+
+#[juniper::object(Context = Context)]
+impl Query {
+    /// All players, optionally filtered by name or dimension
+    fn players(
+        context: &Context,
+        name_contains: Option<String>,
+        dimension: Option<String>,
+        sort_by: Option<PlayerSort>,
+        order: Option<SortOrder>,
+        limit: Option<i32>,
+    ) -> Vec<GqlPlayer> {
+        let name_contains = name_contains.map(|name| name.to_lowercase());
+        let dimension = dimension.map(|d| with_namespace(&d));
+
+        let mut players: Vec<&Arc<Player>> = context
+            .snapshot
+            .players
+            .values()
+            .filter(|player| {
+                name_contains
+                    .as_ref()
+                    .map_or(true, |name| player.name.to_lowercase().contains(name))
+            })
+            .filter(|player| {
+                dimension
+                    .as_ref()
+                    .map_or(true, |d| player.nbt_stats.dimension.as_ref() == Some(d))
+            })
+            .collect();
+
+        let sort_by = sort_by.unwrap_or(PlayerSort::Name);
+        players.sort_by(|a, b| match sort_by {
+            PlayerSort::Name => a.name.cmp(&b.name),
+            PlayerSort::Health => cmp_f64(a.nbt_stats.health, b.nbt_stats.health),
+            PlayerSort::FoodLevel => cmp_f64(a.nbt_stats.food_level, b.nbt_stats.food_level),
+            PlayerSort::XpLevel => cmp_f64(a.nbt_stats.xp_level, b.nbt_stats.xp_level),
+            PlayerSort::Score => cmp_f64(a.nbt_stats.score, b.nbt_stats.score),
+        });
+
+        if order == Some(SortOrder::Desc) {
+            players.reverse();
+        }
+
+        players
+            .into_iter()
+            .take(take_limit(limit))
+            .map(|player| GqlPlayer(player.clone()))
+            .collect()
+    }
+
+    /// A single player by uuid or name
+    fn player(context: &Context, id: String) -> Option<GqlPlayer> {
+        context
+            .snapshot
+            .players
+            .get(&id)
+            .or_else(|| {
+                context
+                    .snapshot
+                    .players
+                    .values()
+                    .find(|player| player.name.eq_ignore_ascii_case(&id))
+            })
+            .map(|player| GqlPlayer(player.clone()))
+    }
+}
+
+pub struct GqlPlayer(Arc<Player>);
+
+#[juniper::object(Context = Context, name = "Player")]
+impl GqlPlayer {
+    fn uuid(&self) -> &str {
+        &self.0.uuid
+    }
+
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    /// Seconds since epoch the player files were last parsed
+    fn last_update(&self, context: &Context) -> Option<f64> {
+        context
+            .snapshot
+            .updated
+            .get(&self.0.uuid)
+            .and_then(|updated| updated.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs_f64())
+    }
+
+    fn nbt(&self) -> &NbtStats {
+        &self.0.nbt_stats
+    }
+
+    /// Stats of a category, by default sorted by value with the highest first
+    fn stats(
+        &self,
+        category: StatCategory,
+        key_contains: Option<String>,
+        sort_by: Option<StatSort>,
+        order: Option<SortOrder>,
+        limit: Option<i32>,
+    ) -> Vec<Stat> {
+        let mut stats: Vec<Stat> = match self.0.stats.get_stat(&category) {
+            Some(stats) => stats
+                .iter()
+                .filter(|(key, _)| {
+                    key_contains
+                        .as_ref()
+                        .map_or(true, |k| key.contains(k.as_str()))
+                })
+                .filter_map(|(key, value)| {
+                    Some(Stat {
+                        key: key.clone(),
+                        value: value.as_f64()?,
+                    })
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        let sort_by = sort_by.unwrap_or(StatSort::Value);
+        stats.sort_by(|a, b| match sort_by {
+            StatSort::Key => a.key.cmp(&b.key),
+            StatSort::Value => cmp_f64(a.value, b.value),
+        });
+
+        let order = order.unwrap_or(match sort_by {
+            StatSort::Key => SortOrder::Asc,
+            StatSort::Value => SortOrder::Desc,
+        });
+        if order == SortOrder::Desc {
+            stats.reverse();
+        }
+
+        stats.truncate(take_limit(limit));
+        stats
+    }
+
+    /// A single stat value, keys may omit the `minecraft:` prefix
+    fn stat(&self, category: StatCategory, key: String) -> Option<f64> {
+        self.0
+            .stats
+            .get_stat(&category)?
+            .get(&with_namespace(&key))?
+            .as_f64()
+    }
+}
+
+fn with_namespace(key: &str) -> String {
+    if key.contains(':') {
+        String::from(key)
+    } else {
+        format!("minecraft:{}", key)
+    }
+}
+
+fn cmp_f64(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+fn take_limit(limit: Option<i32>) -> usize {
+    match limit {
+        Some(limit) if limit >= 0 => limit as usize,
+        _ => usize::MAX,
+    }
+}
+
+lazy_static! {
+    static ref SCHEMA: Schema = Schema::new(Query, EmptyMutation::new());
+}
+
+/// Executes a query from the `query` parameter of a GET or the json body of a POST request
+pub async fn graphql_response(req: Request<Body>, snapshot: Arc<Snapshot>) -> ResponseResult {
+    let request = if req.method() == Method::POST {
+        let content = match read_body(req).await {
+            Ok(content) => content,
+            Err(response) => return response,
+        };
+
+        match serde_json::from_slice::<GraphQLRequest>(&content) {
+            Ok(request) => request,
+            Err(_) => return error_response(StatusCode::BAD_REQUEST, "Invalid GraphQL request"),
+        }
+    } else {
+        match request_from_query(req.uri().query().unwrap_or("")) {
+            Some(request) => request,
+            None => return error_response(StatusCode::BAD_REQUEST, "Missing query parameter"),
+        }
+    };
+
+    let context = Context { snapshot };
+    let response = request.execute(&SCHEMA, &context);
+    let status = if response.is_ok() {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::to_vec(&response).unwrap_or_default(),
+        ))
+}
+
+/// Reads the body up to `MAX_BODY_SIZE` bytes, announced by `Content-Length` or not
+async fn read_body(req: Request<Body>) -> std::result::Result<Vec<u8>, ResponseResult> {
+    let too_large = || {
+        error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            &format!("Request body is larger than {} bytes", MAX_BODY_SIZE),
+        )
+    };
+
+    let length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<usize>().ok());
+    match length {
+        Some(length) if length > MAX_BODY_SIZE => return Err(too_large()),
+        _ => {}
+    }
+
+    let mut body = req.into_body();
+    let mut content = Vec::with_capacity(length.unwrap_or(0));
+    while let Some(chunk) = body.data().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(_) => {
+                return Err(error_response(
+                    StatusCode::BAD_REQUEST,
+                    "Could not read request body",
+                ))
+            }
+        };
+        if content.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(too_large());
+        }
+        content.extend_from_slice(&chunk);
+    }
+
+    Ok(content)
+}
+
+fn request_from_query(query: &str) -> Option<GraphQLRequest> {
+    let mut graphql_query = None;
+    let mut operation_name = None;
+    let mut variables = None;
+
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "query" => graphql_query = Some(value.into_owned()),
+            "operationName" => operation_name = Some(value.into_owned()),
+            "variables" => variables = serde_json::from_str::<InputValue>(&value).ok(),
+            _ => {}
+        }
+    }
+
+    Some(GraphQLRequest::new(
+        graphql_query?,
+        operation_name,
+        variables,
+    ))
+}
+
+fn error_response(status: StatusCode, error: &str) -> ResponseResult {
+    let body = serde_json::json!({ "errors": [{ "message": error }] });
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_player;
+    use crate::prometheus_handler::StatCache;
+    use crate::stats::Stats;
+    use hyper::body;
+    use serde_json::Value;
+
+    fn mock_snapshot() -> Arc<Snapshot> {
+        let mut nether = mock_player!(2);
+        nether.nbt_stats.dimension = Some(String::from("minecraft:the_nether"));
+        nether.nbt_stats.health = 4.0;
+        nether.stats = Stats::from(String::from(
+            "{ \"stats\": { \"minecraft:mined\": {
+                \"minecraft:stone\": 30, \"minecraft:diamond_ore\": 2, \"minecraft:netherrack\": 90
+            } } }",
+        ))
+        .unwrap();
+
+        let cache = StatCache::new().unwrap();
+        cache.update(vec![mock_player!(1), nether]);
+
+        cache.snapshot()
+    }
+
+    async fn execute(query: &str) -> (StatusCode, Value) {
+        let body = serde_json::json!({ "query": query }).to_string();
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/graphql")
+            .body(Body::from(body))
+            .unwrap();
+
+        let response = graphql_response(req, mock_snapshot()).await.unwrap();
+        let status = response.status();
+        let body = body::to_bytes(response.into_body()).await.unwrap();
+
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    mod graphql_response {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_reject_large_bodies() {
+            let query = format!("{{ players {{ name }} }}{}", " ".repeat(MAX_BODY_SIZE));
+            let body = serde_json::json!({ "query": query }).to_string();
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri("/graphql")
+                .body(Body::from(body))
+                .unwrap();
+
+            let response = graphql_response(req, mock_snapshot()).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        #[tokio::test]
+        async fn should_reject_large_bodies_without_length() {
+            let chunks: Vec<std::result::Result<_, std::io::Error>> =
+                vec![Ok(vec![b' '; MAX_BODY_SIZE]), Ok(vec![b' '; 1])];
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri("/graphql")
+                .body(Body::wrap_stream(futures_util::stream::iter(chunks)))
+                .unwrap();
+
+            let response = graphql_response(req, mock_snapshot()).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        #[tokio::test]
+        async fn should_filter_by_dimension_and_limit_stats() {
+            let (status, actual) = execute(
+                "{ players(dimension: \"the_nether\") {
+                    name nbt { health } stats(category: MINED, limit: 2) { key value }
+                } }",
+            )
+            .await;
+            let players = &actual["data"]["players"];
+
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(players.as_array().unwrap().len(), 1);
+            assert_eq!(players[0]["name"], "name-2");
+            assert_eq!(players[0]["nbt"]["health"], 4.0);
+            assert_eq!(players[0]["stats"][0]["key"], "minecraft:netherrack");
+            assert_eq!(players[0]["stats"][1]["key"], "minecraft:stone");
+            assert_eq!(players[0]["stats"].as_array().unwrap().len(), 2);
+        }
+
+        #[tokio::test]
+        async fn should_sort_players() {
+            let (_, actual) = execute("{ players(sortBy: HEALTH, order: DESC) { name } }").await;
+
+            assert_eq!(actual["data"]["players"][0]["name"], "name-1");
+            assert_eq!(actual["data"]["players"][1]["name"], "name-2");
+        }
+
+        #[tokio::test]
+        async fn should_find_single_stat() {
+            let (_, actual) =
+                execute("{ player(id: \"name-2\") { stat(category: MINED, key: \"stone\") } }")
+                    .await;
+
+            assert_eq!(actual["data"]["player"]["stat"], 30.0);
+        }
+
+        #[tokio::test]
+        async fn should_reject_invalid_queries() {
+            let (status, actual) = execute("{ players { unknown } }").await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(actual["errors"].is_array());
+        }
+
+        #[tokio::test]
+        async fn should_accept_get_requests() {
+            let req = Request::builder()
+                .uri("/graphql?query=%7B%20players%20%7B%20uuid%20%7D%20%7D")
+                .body(Body::empty())
+                .unwrap();
+
+            let response = graphql_response(req, mock_snapshot()).await.unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
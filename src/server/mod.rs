@@ -0,0 +1,445 @@
+use crate::prometheus_handler::{Registries, StatCache};
+use crate::Result;
+use api::api_response;
+use auth::is_authorized;
+use compression::Compression;
+use filter::MetricFilter;
+use format::Format;
+use hyper::{
+    header::{CONTENT_ENCODING, CONTENT_TYPE, VARY, WWW_AUTHENTICATE},
+    server::conn::Http,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use prometheus::proto::MetricFamily;
+use std::{
+    fs,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+use streaming::stream_body;
+use tls::TlsReloader;
+use tokio::net::TcpListener;
+pub use web_config::WebConfig;
+use websocket::websocket_response;
+
+mod api;
+mod auth;
+mod compression;
+mod filter;
+mod format;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod open_metrics;
+mod streaming;
+mod tls;
+mod web_config;
+mod websocket;
+
+type ResponseResult = std::result::Result<Response<Body>, hyper::http::Error>;
+
+const LANDING_PAGE: &str = r#"<html>
+<head><title>Minecraft Exporter</title></head>
+<body>
+<h1>Minecraft Exporter</h1>
+<ul>
+<li><a href="/metrics">Metrics</a></li>
+<li><a href="/metrics/exporter">Exporter Metrics</a></li>
+<li><a href="/api/players">Players</a></li>
+</ul>
+</body>
+</html>
+"#;
+
+/// Everything the http handlers need to answer requests
+pub struct State {
+    pub stat_cache: StatCache,
+    pub registries: Registries,
+    /// Serve exporter metrics on `/metrics` next to the game metrics
+    pub merge_metrics: bool,
+    pub world_path: PathBuf,
+    pub web_config: WebConfig,
+    /// Set once the first scrape finished successfully
+    pub scraped: AtomicBool,
+    pub started: SystemTime,
+}
+
+impl State {
+    pub fn new(
+        stat_cache: StatCache,
+        merge_metrics: bool,
+        world_path: PathBuf,
+        web_config: WebConfig,
+    ) -> Result<Self> {
+        Ok(Self {
+            registries: Registries::new(&stat_cache)?,
+            stat_cache,
+            merge_metrics,
+            world_path,
+            web_config,
+            scraped: AtomicBool::new(false),
+            started: SystemTime::now(),
+        })
+    }
+
+    pub fn set_scraped(&self) {
+        self.scraped.store(true, Ordering::Relaxed);
+    }
+
+    fn is_ready(&self) -> bool {
+        self.scraped.load(Ordering::Relaxed) && fs::read_dir(&self.world_path).is_ok()
+    }
+}
+
+pub async fn run_server(addr: SocketAddr, state: Arc<State>) -> Result<()> {
+    if let Some(tls) = &state.web_config.tls_server_config {
+        let tls = TlsReloader::new(tls.clone())?;
+        return run_tls_server(addr, state, tls).await;
+    }
+
+    info!("Listening on http://{}", addr);
+
+    let make_svc = make_service_fn(move |_| {
+        let state = state.clone();
+
+        async move { Ok::<_, hyper::Error>(service_fn(move |req| serve_req(req, state.clone()))) }
+    });
+
+    // Then bind and serve...
+    Server::bind(&addr)
+        .serve(make_svc)
+        .await
+        .map_err(|e| e.into())
+}
+
+async fn run_tls_server(addr: SocketAddr, state: Arc<State>, tls: TlsReloader) -> Result<()> {
+    info!("Listening on https://{}", addr);
+
+    let mut listener = TcpListener::bind(&addr).await?;
+
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                error!("Could not accept connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = match tls.acceptor() {
+            Ok(acceptor) => acceptor,
+            Err(e) => {
+                error!("Could not load TLS certificate: {}", e);
+                continue;
+            }
+        };
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(socket).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("TLS handshake failed: {}", e);
+                    return;
+                }
+            };
+
+            let service = service_fn(move |req| serve_req(req, state.clone()));
+
+            if let Err(e) = Http::new()
+                .serve_connection(stream, service)
+                .with_upgrades()
+                .await
+            {
+                debug!("Connection error: {}", e);
+            }
+        });
+    }
+}
+
+async fn serve_req(req: Request<Body>, state: Arc<State>) -> ResponseResult {
+    let path = req.uri().path();
+    // Queries may also be posted as json to the GraphQL endpoint
+    let allows_post = cfg!(feature = "graphql") && path == "/graphql";
+
+    if req.method() != Method::GET
+        && req.method() != Method::HEAD
+        && !(allows_post && req.method() == Method::POST)
+    {
+        return text_response(StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed");
+    }
+
+    // Probes stay reachable without credentials
+    let is_probe = path == "/healthz" || path == "/readyz";
+
+    if !is_probe && !is_authorized(&state.web_config.basic_auth_users, &req).await {
+        return Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(WWW_AUTHENTICATE, "Basic")
+            .body(Body::from("Unauthorized"));
+    }
+
+    #[cfg(feature = "graphql")]
+    {
+        if req.uri().path() == "/graphql" {
+            return graphql::graphql_response(req, state.stat_cache.snapshot()).await;
+        }
+    }
+
+    if req.uri().path() == "/ws/stats" {
+        return websocket_response(req, &state.stat_cache);
+    }
+
+    match req.uri().path() {
+        "/" => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .body(Body::from(LANDING_PAGE)),
+        "/healthz" => text_response(StatusCode::OK, "OK"),
+        "/readyz" if state.is_ready() => text_response(StatusCode::OK, "OK"),
+        "/readyz" => text_response(StatusCode::SERVICE_UNAVAILABLE, "Not Ready"),
+        "/metrics" => metrics_response(&req, &state, game_families(&req, &state)),
+        "/metrics/exporter" => {
+            let filter = MetricFilter::from_query(req.uri().query());
+            metrics_response(
+                &req,
+                &state,
+                filter.apply(state.registries.exporter.gather()),
+            )
+        }
+        path if path.starts_with("/api/") => api_response(path, &state.stat_cache.snapshot()),
+        _ => text_response(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+/// Game metrics, only rendering the families and players asked for
+fn game_families(req: &Request<Body>, state: &State) -> Vec<MetricFamily> {
+    let filter = MetricFilter::from_query(req.uri().query());
+    let mut families = state
+        .registries
+        .gather_game_matching(&|family| filter.keeps_family(family), &|player| {
+            filter.keeps_player(player)
+        });
+
+    if state.merge_metrics {
+        families.extend(filter.apply(state.registries.exporter.gather()));
+    }
+
+    families
+}
+
+/// Encodes the already filtered `metric_families` in the format and compression asked for
+fn metrics_response(
+    req: &Request<Body>,
+    state: &State,
+    metric_families: Vec<MetricFamily>,
+) -> ResponseResult {
+    let format = Format::from_headers(req.headers());
+    let compression = Compression::from_headers(req.headers());
+    let snapshot = state.stat_cache.snapshot();
+    let player_families = state.stat_cache.family_names();
+    let started = state.started;
+
+    let body = stream_body(move |writer| {
+        compression.compress(writer, |mut writer| {
+            let created = snapshot.created_by_player();
+            format.encode(
+                &metric_families,
+                created,
+                &player_families,
+                started,
+                &mut writer,
+            )
+        })
+    });
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, format.content_type())
+        .header(VARY, "Accept-Encoding");
+
+    if let Some(encoding) = compression.content_encoding() {
+        response = response.header(CONTENT_ENCODING, encoding);
+    }
+
+    response.body(body)
+}
+
+fn text_response(status: StatusCode, text: &'static str) -> ResponseResult {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn mock_state(world_path: PathBuf) -> Arc<State> {
+        mock_state_with_config(world_path, WebConfig::default())
+    }
+
+    fn mock_state_with_config(world_path: PathBuf, web_config: WebConfig) -> Arc<State> {
+        let stat_cache = StatCache::new().unwrap();
+
+        Arc::new(State::new(stat_cache, false, world_path, web_config).unwrap())
+    }
+
+    async fn status_of(state: Arc<State>, method: Method, path: &str) -> StatusCode {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+
+        serve_req(req, state).await.unwrap().status()
+    }
+
+    mod serve_req {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_answer_known_paths() {
+            let state = mock_state(env::temp_dir());
+
+            for path in &["/", "/healthz", "/metrics", "/metrics/exporter"] {
+                let actual = status_of(state.clone(), Method::GET, path).await;
+
+                assert_eq!(actual, StatusCode::OK);
+            }
+        }
+
+        #[tokio::test]
+        async fn should_return_not_found_for_unknown_path() {
+            let state = mock_state(env::temp_dir());
+
+            let actual = status_of(state, Method::GET, "/unknown").await;
+
+            assert_eq!(actual, StatusCode::NOT_FOUND);
+        }
+
+        #[tokio::test]
+        async fn should_reject_other_methods() {
+            let state = mock_state(env::temp_dir());
+
+            let actual = status_of(state, Method::POST, "/metrics").await;
+
+            assert_eq!(actual, StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        #[tokio::test]
+        async fn should_not_be_ready_before_first_scrape() {
+            let state = mock_state(env::temp_dir());
+
+            let actual = status_of(state, Method::GET, "/readyz").await;
+
+            assert_eq!(actual, StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        #[tokio::test]
+        async fn should_be_ready_after_first_scrape() {
+            let state = mock_state(env::temp_dir());
+            state.set_scraped();
+
+            let actual = status_of(state, Method::GET, "/readyz").await;
+
+            assert_eq!(actual, StatusCode::OK);
+        }
+
+        #[tokio::test]
+        async fn should_not_be_ready_without_world() {
+            let state = mock_state(env::temp_dir().join("missing-minecraft-world"));
+            state.set_scraped();
+
+            let actual = status_of(state, Method::GET, "/readyz").await;
+
+            assert_eq!(actual, StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        #[tokio::test]
+        async fn should_negotiate_open_metrics() {
+            let state = mock_state(env::temp_dir());
+            let req = Request::builder()
+                .uri("/metrics")
+                .header("Accept", "application/openmetrics-text; version=1.0.0")
+                .body(Body::empty())
+                .unwrap();
+
+            let actual = serve_req(req, state).await.unwrap();
+            let content_type = actual.headers()[CONTENT_TYPE].to_str().unwrap().to_string();
+            let body = hyper::body::to_bytes(actual.into_body()).await.unwrap();
+
+            assert!(content_type.starts_with("application/openmetrics-text"));
+            assert!(body.ends_with(b"# EOF\n"));
+        }
+
+        #[tokio::test]
+        async fn should_gzip_metrics() {
+            use flate2::read::GzDecoder;
+            use std::io::Read;
+
+            let state = mock_state(env::temp_dir());
+            let req = Request::builder()
+                .uri("/metrics/exporter")
+                .header("Accept-Encoding", "gzip")
+                .body(Body::empty())
+                .unwrap();
+
+            let actual = serve_req(req, state).await.unwrap();
+            let encoding = actual.headers()[CONTENT_ENCODING]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let body = hyper::body::to_bytes(actual.into_body()).await.unwrap();
+
+            let mut metrics = String::new();
+            GzDecoder::new(&body[..])
+                .read_to_string(&mut metrics)
+                .unwrap();
+
+            assert_eq!(encoding, "gzip");
+            assert!(metrics.contains("mc_exporter_counter_resets_total 0"));
+        }
+
+        #[tokio::test]
+        async fn should_filter_game_metrics_by_player() {
+            use prometheus::{GaugeVec, Opts};
+
+            let state = mock_state(env::temp_dir());
+            let online =
+                GaugeVec::new(Opts::new("mc_player_online", "online"), &["player"]).unwrap();
+            online.with_label_values(&["Alice"]).set(1.0);
+            online.with_label_values(&["Bob"]).set(1.0);
+            state.registries.game.register(Box::new(online)).unwrap();
+            let req = Request::builder()
+                .uri("/metrics?player=Alice")
+                .body(Body::empty())
+                .unwrap();
+
+            let actual = serve_req(req, state).await.unwrap();
+            let body = hyper::body::to_bytes(actual.into_body()).await.unwrap();
+            let metrics = String::from_utf8_lossy(&body);
+
+            assert!(metrics.contains("mc_player_online{player=\"Alice\"} 1"));
+            assert!(!metrics.contains("Bob"));
+        }
+
+        #[tokio::test]
+        async fn should_require_auth_when_users_configured() {
+            let web_config = WebConfig::from("basic_auth_users:\n  alice: hash\n").unwrap();
+            let state = mock_state_with_config(env::temp_dir(), web_config);
+
+            let metrics = status_of(state.clone(), Method::GET, "/metrics").await;
+            let health = status_of(state, Method::GET, "/healthz").await;
+
+            assert_eq!(metrics, StatusCode::UNAUTHORIZED);
+            assert_eq!(health, StatusCode::OK);
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use crate::Result;
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const OPEN_METRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Encodes metric families in the OpenMetrics text format.
+/// Counters, histograms and summaries of `player_families` get a `_created` sample from the time
+/// their player was first seen or last reset, all others fall back to `default_created`.
+pub struct OpenMetricsEncoder<'a> {
+    created_by_player: HashMap<&'a str, SystemTime>,
+    player_families: &'a HashSet<String>,
+    default_created: SystemTime,
+}
+
+impl<'a> OpenMetricsEncoder<'a> {
+    pub fn new(
+        created_by_player: HashMap<&'a str, SystemTime>,
+        player_families: &'a HashSet<String>,
+        default_created: SystemTime,
+    ) -> Self {
+        Self {
+            created_by_player,
+            player_families,
+            default_created,
+        }
+    }
+
+    pub fn encode<W: Write>(&self, families: &[MetricFamily], writer: &mut W) -> Result<()> {
+        for family in families {
+            let field_type = family.get_field_type();
+            let name = family_name(family);
+            let by_player = self.player_families.contains(family.get_name());
+
+            writeln!(writer, "# HELP {} {}", name, escape(family.get_help()))?;
+            writeln!(writer, "# TYPE {} {}", name, type_name(field_type))?;
+
+            for metric in family.get_metric() {
+                self.encode_metric(writer, name, field_type, by_player, metric)?;
+            }
+        }
+
+        writeln!(writer, "# EOF")?;
+
+        Ok(())
+    }
+
+    fn encode_metric<W: Write>(
+        &self,
+        writer: &mut W,
+        name: &str,
+        field_type: MetricType,
+        by_player: bool,
+        metric: &Metric,
+    ) -> Result<()> {
+        let labels = metric.get_label();
+
+        match field_type {
+            MetricType::COUNTER => {
+                let value = metric.get_counter().get_value();
+                write_sample(writer, name, "_total", labels, None, value, metric)?;
+            }
+            MetricType::GAUGE => {
+                let value = metric.get_gauge().get_value();
+                write_sample(writer, name, "", labels, None, value, metric)?;
+            }
+            MetricType::UNTYPED => {
+                let value = metric.get_untyped().get_value();
+                write_sample(writer, name, "", labels, None, value, metric)?;
+            }
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+                let mut has_inf = false;
+
+                for bucket in histogram.get_bucket() {
+                    let upper_bound = bucket.get_upper_bound();
+                    has_inf = has_inf || upper_bound == f64::INFINITY;
+
+                    let le = format_float(upper_bound);
+                    let count = bucket.get_cumulative_count() as f64;
+                    write_sample(
+                        writer,
+                        name,
+                        "_bucket",
+                        labels,
+                        Some(("le", &le)),
+                        count,
+                        metric,
+                    )?;
+                }
+
+                let count = histogram.get_sample_count() as f64;
+                if !has_inf {
+                    write_sample(
+                        writer,
+                        name,
+                        "_bucket",
+                        labels,
+                        Some(("le", "+Inf")),
+                        count,
+                        metric,
+                    )?;
+                }
+
+                write_sample(writer, name, "_count", labels, None, count, metric)?;
+                let sum = histogram.get_sample_sum();
+                write_sample(writer, name, "_sum", labels, None, sum, metric)?;
+            }
+            MetricType::SUMMARY => {
+                let summary = metric.get_summary();
+
+                for quantile in summary.get_quantile() {
+                    let q = format_float(quantile.get_quantile());
+                    let value = quantile.get_value();
+                    write_sample(
+                        writer,
+                        name,
+                        "",
+                        labels,
+                        Some(("quantile", &q)),
+                        value,
+                        metric,
+                    )?;
+                }
+
+                let count = summary.get_sample_count() as f64;
+                write_sample(writer, name, "_count", labels, None, count, metric)?;
+                let sum = summary.get_sample_sum();
+                write_sample(writer, name, "_sum", labels, None, sum, metric)?;
+            }
+        }
+
+        if field_type != MetricType::GAUGE && field_type != MetricType::UNTYPED {
+            let created = if by_player {
+                self.created(labels)
+            } else {
+                self.default_created
+            };
+            let created = seconds_since_epoch(created);
+            write_sample(writer, name, "_created", labels, None, created, metric)?;
+        }
+
+        Ok(())
+    }
+
+    fn created(&self, labels: &[LabelPair]) -> SystemTime {
+        labels
+            .iter()
+            .find(|label| label.get_name() == "player")
+            .and_then(|label| self.created_by_player.get(label.get_value()))
+            .copied()
+            .unwrap_or(self.default_created)
+    }
+}
+
+/// Counters are exposed as `<name>_total`, so their family name must not contain the suffix
+fn family_name(family: &MetricFamily) -> &str {
+    let name = family.get_name();
+
+    if family.get_field_type() == MetricType::COUNTER && name.ends_with("_total") {
+        &name[..name.len() - "_total".len()]
+    } else {
+        name
+    }
+}
+
+fn type_name(field_type: MetricType) -> &'static str {
+    match field_type {
+        MetricType::COUNTER => "counter",
+        MetricType::GAUGE => "gauge",
+        MetricType::HISTOGRAM => "histogram",
+        MetricType::SUMMARY => "summary",
+        MetricType::UNTYPED => "unknown",
+    }
+}
+
+fn write_sample<W: Write>(
+    writer: &mut W,
+    name: &str,
+    suffix: &str,
+    labels: &[LabelPair],
+    extra_label: Option<(&str, &str)>,
+    value: f64,
+    metric: &Metric,
+) -> Result<()> {
+    write!(writer, "{}{}", name, suffix)?;
+
+    let mut pairs: Vec<(&str, &str)> = labels
+        .iter()
+        .map(|label| (label.get_name(), label.get_value()))
+        .collect();
+    pairs.extend(extra_label);
+
+    if !pairs.is_empty() {
+        let pairs: Vec<String> = pairs
+            .iter()
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect();
+
+        write!(writer, "{{{}}}", pairs.join(","))?;
+    }
+
+    write!(writer, " {}", format_float(value))?;
+
+    if metric.has_timestamp_ms() {
+        write!(writer, " {}", metric.get_timestamp_ms() as f64 / 1000.0)?;
+    }
+
+    writeln!(writer)?;
+
+    Ok(())
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('"', "\\\"")
+}
+
+fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        String::from("NaN")
+    } else if value == f64::INFINITY {
+        String::from("+Inf")
+    } else if value == f64::NEG_INFINITY {
+        String::from("-Inf")
+    } else {
+        format!("{}", value)
+    }
+}
+
+fn seconds_since_epoch(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Counter, Gauge, Histogram, HistogramOpts, Opts, Registry};
+    use std::time::Duration;
+
+    fn encode(registry: &Registry, encoder: &OpenMetricsEncoder) -> String {
+        let mut buffer = vec![];
+        encoder.encode(&registry.gather(), &mut buffer).unwrap();
+
+        String::from_utf8(buffer).unwrap()
+    }
+
+    fn epoch_plus(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    mod encode {
+        use super::*;
+
+        #[test]
+        fn should_end_with_eof() {
+            let player_families = HashSet::new();
+            let encoder = OpenMetricsEncoder::new(HashMap::new(), &player_families, epoch_plus(0));
+
+            let actual = encode(&Registry::new(), &encoder);
+
+            assert_eq!(actual, "# EOF\n");
+        }
+
+        #[test]
+        fn should_suffix_counters_and_add_created() {
+            let registry = Registry::new();
+            let counter = Counter::with_opts(
+                Opts::new("mc_mined", "mined blocks").const_label("player", "alice"),
+            )
+            .unwrap();
+            counter.inc_by(3.0);
+            registry.register(Box::new(counter)).unwrap();
+
+            let mut created = HashMap::new();
+            created.insert("alice", epoch_plus(100));
+            let player_families = vec![String::from("mc_mined")].into_iter().collect();
+            let encoder = OpenMetricsEncoder::new(created, &player_families, epoch_plus(1));
+
+            let actual = encode(&registry, &encoder);
+            let expected = "# HELP mc_mined mined blocks
+# TYPE mc_mined counter
+mc_mined_total{player=\"alice\"} 3
+mc_mined_created{player=\"alice\"} 100
+# EOF
+";
+
+            assert_eq!(actual, expected);
+        }
+
+        #[test]
+        fn should_not_use_player_created_for_other_families() {
+            let registry = Registry::new();
+            let counter = Counter::with_opts(
+                Opts::new("mc_player_deaths_total", "deaths").const_label("player", "alice"),
+            )
+            .unwrap();
+            registry.register(Box::new(counter)).unwrap();
+
+            let mut created = HashMap::new();
+            created.insert("alice", epoch_plus(100));
+            let player_families = vec![String::from("mc_mined")].into_iter().collect();
+            let encoder = OpenMetricsEncoder::new(created, &player_families, epoch_plus(1));
+
+            let actual = encode(&registry, &encoder);
+
+            assert!(actual.contains("mc_player_deaths_created{player=\"alice\"} 1\n"));
+        }
+
+        #[test]
+        fn should_strip_total_from_counter_family() {
+            let registry = Registry::new();
+            let counter = Counter::new("resets_total", "resets").unwrap();
+            registry.register(Box::new(counter)).unwrap();
+            let player_families = HashSet::new();
+            let encoder = OpenMetricsEncoder::new(HashMap::new(), &player_families, epoch_plus(5));
+
+            let actual = encode(&registry, &encoder);
+
+            assert!(actual.contains("# TYPE resets counter\n"));
+            assert!(actual.contains("resets_total 0\n"));
+            assert!(actual.contains("resets_created 5\n"));
+        }
+
+        #[test]
+        fn should_not_add_created_to_gauges() {
+            let registry = Registry::new();
+            let gauge = Gauge::new("mc_health", "health \"now\"").unwrap();
+            gauge.set(20.0);
+            registry.register(Box::new(gauge)).unwrap();
+            let player_families = HashSet::new();
+            let encoder = OpenMetricsEncoder::new(HashMap::new(), &player_families, epoch_plus(5));
+
+            let actual = encode(&registry, &encoder);
+            let expected = "# HELP mc_health health \\\"now\\\"
+# TYPE mc_health gauge
+mc_health 20
+# EOF
+";
+
+            assert_eq!(actual, expected);
+        }
+
+        #[test]
+        fn should_encode_histogram_buckets() {
+            let registry = Registry::new();
+            let histogram =
+                Histogram::with_opts(HistogramOpts::new("session", "length").buckets(vec![1.0]))
+                    .unwrap();
+            histogram.observe(0.5);
+            registry.register(Box::new(histogram)).unwrap();
+            let player_families = HashSet::new();
+            let encoder = OpenMetricsEncoder::new(HashMap::new(), &player_families, epoch_plus(5));
+
+            let actual = encode(&registry, &encoder);
+
+            assert!(actual.contains("session_bucket{le=\"1\"} 1\n"));
+            assert!(actual.contains("session_bucket{le=\"+Inf\"} 1\n"));
+            assert!(actual.contains("session_count 1\n"));
+            assert!(actual.contains("session_sum 0.5\n"));
+            assert!(actual.contains("session_created 5\n"));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::Result;
+use hyper::{body::Bytes, Body};
+use std::io::{self, Write};
+use tokio::{runtime::Handle, sync::mpsc, task};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks buffered before the encoder waits for the client to catch up
+const CHUNKS_IN_FLIGHT: usize = 4;
+
+type Chunk = std::result::Result<Bytes, io::Error>;
+
+/// Runs `write` on a blocking thread and streams everything it writes as response body
+pub fn stream_body<F>(write: F) -> Body
+where
+    F: FnOnce(&mut ChannelWriter) -> Result<()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(CHUNKS_IN_FLIGHT);
+    let handle = Handle::current();
+
+    task::spawn_blocking(move || {
+        let mut writer = ChannelWriter::new(sender, handle);
+
+        if let Err(e) = write(&mut writer) {
+            debug!("Could not stream response body: {}", e);
+            writer.abort(&e.to_string());
+        } else if let Err(e) = writer.flush() {
+            debug!("Could not stream response body: {}", e);
+        }
+    });
+
+    Body::wrap_stream(receiver)
+}
+
+/// Writer sending its data in chunks to a streamed response body
+pub struct ChannelWriter {
+    sender: mpsc::Sender<Chunk>,
+    handle: Handle,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn new(sender: mpsc::Sender<Chunk>, handle: Handle) -> Self {
+        Self {
+            sender,
+            handle,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    /// Blocks the encoding thread until the body has room for another chunk
+    fn send(&mut self, chunk: Chunk) -> io::Result<()> {
+        let sender = &mut self.sender;
+
+        self.handle
+            .block_on(sender.send(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Response body dropped"))
+    }
+
+    fn send_buffer(&mut self) -> io::Result<()> {
+        let chunk = Bytes::from(std::mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(CHUNK_SIZE),
+        ));
+
+        self.send(Ok(chunk))
+    }
+
+    /// Ends the body with an error, so clients don't mistake it for a complete response
+    fn abort(&mut self, reason: &str) {
+        let error = io::Error::new(io::ErrorKind::Other, reason.to_string());
+        self.send(Err(error)).ok();
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            Ok(())
+        } else {
+            self.send_buffer()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod stream_body {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_stream_more_chunks_than_buffered() {
+            let body = stream_body(|writer| {
+                for _ in 0..CHUNKS_IN_FLIGHT * 3 {
+                    writer.write_all(&[b'x'; CHUNK_SIZE])?;
+                }
+
+                Ok(())
+            });
+
+            let actual = hyper::body::to_bytes(body).await.unwrap();
+
+            assert_eq!(actual.len(), CHUNKS_IN_FLIGHT * 3 * CHUNK_SIZE);
+        }
+
+        #[tokio::test]
+        async fn should_end_body_with_error() {
+            let body = stream_body(|writer| {
+                writer.write_all(b"partial")?;
+                Err("Encoding failed")?
+            });
+
+            assert!(hyper::body::to_bytes(body).await.is_err());
+        }
+    }
+}
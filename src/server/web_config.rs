@@ -0,0 +1,94 @@
+use crate::Result;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Web configuration in the format of the Prometheus exporter-toolkit
+#[derive(Debug, Default, Deserialize)]
+pub struct WebConfig {
+    pub tls_server_config: Option<TlsServerConfig>,
+    /// Usernames mapped to bcrypt hashed passwords
+    #[serde(default)]
+    pub basic_auth_users: HashMap<String, String>,
+}
+
+/// Only server-side TLS is supported, other keys like `client_ca_file` are rejected instead of
+/// silently serving without them
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsServerConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+}
+
+impl WebConfig {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let mut config = WebConfig::from(&content)?;
+
+        // Like the exporter-toolkit, files are relative to the config file
+        if let (Some(tls), Some(dir)) = (&mut config.tls_server_config, path.parent()) {
+            tls.cert_file = dir.join(&tls.cert_file);
+            tls.key_file = dir.join(&tls.key_file);
+        }
+
+        Ok(config)
+    }
+
+    pub fn from(content: &str) -> Result<Self> {
+        let config: WebConfig = serde_yaml::from_str(content)?;
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod from {
+        use super::*;
+
+        #[test]
+        fn should_parse_tls_and_users() {
+            let content = "
+tls_server_config:
+  cert_file: server.crt
+  key_file: server.key
+basic_auth_users:
+  alice: $2y$10$hash
+";
+
+            let actual = WebConfig::from(content).unwrap();
+            let tls = actual.tls_server_config.unwrap();
+
+            assert_eq!(tls.cert_file, PathBuf::from("server.crt"));
+            assert_eq!(tls.key_file, PathBuf::from("server.key"));
+            assert_eq!(actual.basic_auth_users["alice"], "$2y$10$hash");
+        }
+
+        #[test]
+        fn should_reject_client_auth() {
+            let content = "
+tls_server_config:
+  cert_file: server.crt
+  key_file: server.key
+  client_auth_type: RequireAndVerifyClientCert
+  client_ca_file: ca.crt
+";
+
+            assert!(WebConfig::from(content).is_err());
+        }
+
+        #[test]
+        fn should_allow_missing_sections() {
+            let actual = WebConfig::from("basic_auth_users:\n  bob: hash\n").unwrap();
+
+            assert!(actual.tls_server_config.is_none());
+            assert_eq!(actual.basic_auth_users.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use crate::prometheus_handler::{StatCache, StatChange};
+use crate::stats::StatCategory;
+use futures_util::{SinkExt, StreamExt};
+use hyper::{
+    header::{CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE},
+    upgrade::Upgraded,
+    Body, Request, Response, StatusCode,
+};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use tokio::sync::broadcast::{self, RecvError};
+use tokio_tungstenite::{
+    tungstenite::protocol::{Message, Role},
+    WebSocketStream,
+};
+use url::form_urlencoded;
+
+type ResponseResult = std::result::Result<Response<Body>, hyper::http::Error>;
+
+/// Appended to the client key before hashing, see RFC 6455 section 1.3
+const ACCEPT_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Restricts streamed changes by `player=<name>` and `category=<category>` query parameters
+#[derive(Debug, Default)]
+pub struct ChangeFilter {
+    players: HashSet<String>,
+    categories: HashSet<StatCategory>,
+}
+
+impl ChangeFilter {
+    pub fn from_query(query: Option<&str>) -> Self {
+        let mut filter = ChangeFilter::default();
+
+        for (key, value) in form_urlencoded::parse(query.unwrap_or("").as_bytes()) {
+            match key.as_ref() {
+                "player" => {
+                    filter.players.insert(value.into_owned());
+                }
+                "category" => match StatCategory::from_name(&value) {
+                    Some(category) => {
+                        filter.categories.insert(category);
+                    }
+                    None => warn!("Ignoring unknown stat category {}", value),
+                },
+                _ => {}
+            }
+        }
+
+        filter
+    }
+
+    pub fn matches(&self, change: &StatChange) -> bool {
+        (self.players.is_empty() || self.players.contains(&change.player))
+            && (self.categories.is_empty() || self.categories.contains(&change.diff.category))
+    }
+}
+
+/// Upgrades the connection and pushes every matching stat change as json text message
+pub fn websocket_response(req: Request<Body>, stat_cache: &StatCache) -> ResponseResult {
+    let is_websocket = req
+        .headers()
+        .get(UPGRADE)
+        .and_then(|upgrade| upgrade.to_str().ok())
+        .map_or(false, |upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+    let key = match req.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) if is_websocket => accept_key(key.as_bytes()),
+        _ => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Expected WebSocket upgrade"))
+        }
+    };
+
+    let filter = ChangeFilter::from_query(req.uri().query());
+    // Subscribe before answering so no change between handshake and upgrade is lost
+    let changes = stat_cache.subscribe();
+
+    tokio::spawn(async move {
+        match req.into_body().on_upgrade().await {
+            Ok(upgraded) => stream_changes(upgraded, filter, changes).await,
+            Err(e) => debug!("WebSocket upgrade failed: {}", e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(UPGRADE, "websocket")
+        .header(CONNECTION, "Upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, key)
+        .body(Body::empty())
+}
+
+async fn stream_changes(
+    upgraded: Upgraded,
+    filter: ChangeFilter,
+    mut changes: broadcast::Receiver<StatChange>,
+) {
+    let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+    let (mut sink, mut stream) = socket.split();
+
+    loop {
+        tokio::select! {
+            change = changes.recv() => match change {
+                Ok(change) if filter.matches(&change) => {
+                    let text = match serde_json::to_string(&change) {
+                        Ok(text) => text,
+                        Err(e) => {
+                            warn!("Could not serialize stat change: {}", e);
+                            continue;
+                        }
+                    };
+
+                    if let Err(e) = sink.send(Message::Text(text)).await {
+                        debug!("Could not send stat change: {}", e);
+                        break;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("WebSocket client too slow, skipped {} stat changes", skipped)
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = stream.next() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by tungstenite, anything else is ignored
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    let _ = sink.close().await;
+}
+
+fn accept_key(key: &[u8]) -> String {
+    let mut sha1 = Sha1::default();
+    sha1.input(key);
+    sha1.input(ACCEPT_GUID);
+
+    base64::encode(&sha1.result())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::Player;
+    use crate::stats::{StatDiff, Stats};
+    use crate::{mock_custom_stats, mock_player};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::Server;
+    use tokio::net::TcpStream;
+
+    fn mock_change(player: &str, category: StatCategory) -> StatChange {
+        StatChange {
+            player: String::from(player),
+            diff: StatDiff {
+                category,
+                key: String::from("minecraft:stone"),
+                old: Some(1.0),
+                new: 2.0,
+            },
+        }
+    }
+
+    fn changed_player(id: u32) -> Player {
+        let mut player = mock_player!(id);
+        player.stats = Stats::from(String::from(
+            "{ \"stats\": { \"minecraft:custom\": { \"minecraft:testo\": 43 } } }",
+        ))
+        .unwrap();
+
+        player
+    }
+
+    mod change_filter {
+        use super::*;
+
+        #[test]
+        fn should_match_everything_without_query() {
+            let filter = ChangeFilter::from_query(None);
+
+            assert!(filter.matches(&mock_change("name-1", StatCategory::Mined)));
+        }
+
+        #[test]
+        fn should_filter_by_player_and_category() {
+            let filter =
+                ChangeFilter::from_query(Some("player=name-1&category=mined&category=used"));
+
+            assert!(filter.matches(&mock_change("name-1", StatCategory::Mined)));
+            assert!(filter.matches(&mock_change("name-1", StatCategory::Used)));
+            assert!(!filter.matches(&mock_change("name-2", StatCategory::Mined)));
+            assert!(!filter.matches(&mock_change("name-1", StatCategory::Crafted)));
+        }
+
+        #[test]
+        fn should_accept_prefixed_categories() {
+            let filter = ChangeFilter::from_query(Some("category=minecraft%3Amined"));
+
+            assert!(filter.matches(&mock_change("name-1", StatCategory::Mined)));
+        }
+    }
+
+    mod accept_key {
+        use super::*;
+
+        #[test]
+        fn should_hash_rfc_example() {
+            let actual = accept_key(b"dGhlIHNhbXBsZSBub25jZQ==");
+
+            assert_eq!(actual, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        }
+    }
+
+    mod websocket_response {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_reject_plain_requests() {
+            let cache = StatCache::new().unwrap();
+            let req = Request::builder()
+                .uri("/ws/stats")
+                .body(Body::empty())
+                .unwrap();
+
+            let actual = websocket_response(req, &cache).unwrap();
+
+            assert_eq!(actual.status(), StatusCode::BAD_REQUEST);
+        }
+
+        #[tokio::test]
+        async fn should_push_matching_changes() {
+            let cache = StatCache::new().unwrap();
+            cache.update(vec![
+                mock_player!(1, mock_custom_stats!()),
+                mock_player!(2, mock_custom_stats!()),
+            ]);
+
+            let server_cache = cache.clone();
+            let make_svc = make_service_fn(move |_| {
+                let cache = server_cache.clone();
+
+                async move {
+                    Ok::<_, hyper::Error>(service_fn(move |req| {
+                        let response = websocket_response(req, &cache);
+                        async move { response }
+                    }))
+                }
+            });
+            let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+            let addr = server.local_addr();
+            tokio::spawn(server);
+
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let url = format!("ws://{}/ws/stats?player=name-1", addr);
+            let (mut socket, _) = tokio_tungstenite::client_async(url.as_str(), stream)
+                .await
+                .unwrap();
+
+            cache.update(vec![changed_player(2), changed_player(1)]);
+
+            let message = socket.next().await.unwrap().unwrap();
+            let actual: serde_json::Value =
+                serde_json::from_str(message.to_text().unwrap()).unwrap();
+
+            assert_eq!(actual["player"], "name-1");
+            assert_eq!(actual["category"], "minecraft:custom");
+            assert_eq!(actual["key"], "minecraft:testo");
+            assert_eq!(actual["old"], 42.0);
+            assert_eq!(actual["new"], 43.0);
+        }
+    }
+}
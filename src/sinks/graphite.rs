@@ -0,0 +1,145 @@
+use crate::sinks::series::{to_series, Series};
+use crate::Result;
+use prometheus::proto::MetricFamily;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{io::AsyncWriteExt, net::TcpStream, time};
+
+/// Writes metrics in the Graphite plaintext protocol over TCP, labels become tags
+pub struct Graphite {
+    addr: String,
+    prefix: String,
+}
+
+impl Graphite {
+    pub fn new(addr: &str, prefix: &str) -> Self {
+        Self {
+            addr: String::from(addr),
+            prefix: prefix.trim_end_matches('.').to_string(),
+        }
+    }
+
+    pub async fn write(&self, families: &[MetricFamily]) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut lines = String::new();
+
+        for series in to_series(families, &[]) {
+            lines.push_str(&to_line(&self.prefix, &series, timestamp));
+            lines.push('\n');
+        }
+
+        let mut stream = time::timeout(
+            Duration::from_secs(10),
+            TcpStream::connect(self.addr.as_str()),
+        )
+        .await??;
+        stream.write_all(lines.as_bytes()).await?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+
+        Ok(())
+    }
+}
+
+/// `minecraft.mc_mined;player=Alice;type=minecraft:stone 30 1589000000`
+fn to_line(prefix: &str, series: &Series, timestamp: u64) -> String {
+    let mut path = if prefix.is_empty() {
+        String::from(series.name())
+    } else {
+        format!("{}.{}", prefix, series.name())
+    };
+
+    for (name, value) in series.tags() {
+        if value.is_empty() {
+            continue;
+        }
+
+        path.push(';');
+        path.push_str(&sanitize(name, &[';', '!', '^', '=']));
+        path.push('=');
+        path.push_str(sanitize(value, &[';']).trim_start_matches('~'));
+    }
+
+    format!("{} {} {}", path, series.value, timestamp)
+}
+
+/// Replaces whitespace and characters Graphite does not allow in tags
+fn sanitize(value: &str, special: &[char]) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_whitespace() || special.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{CounterVec, Opts, Registry};
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    fn mock_families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let counter =
+            CounterVec::new(Opts::new("mc_mined", "mined help"), &["player", "type"]).unwrap();
+        counter
+            .with_label_values(&["Alice Doe", "minecraft:stone"])
+            .inc_by(30.0);
+        registry.register(Box::new(counter)).unwrap();
+
+        registry.gather()
+    }
+
+    mod to_line {
+        use super::*;
+
+        #[test]
+        fn should_use_labels_as_tags() {
+            let series = &to_series(&mock_families(), &[])[0];
+
+            let actual = to_line("minecraft", series, 1000);
+            let expected = "minecraft.mc_mined;player=Alice_Doe;type=minecraft:stone 30 1000";
+
+            assert_eq!(actual, expected);
+        }
+
+        #[test]
+        fn should_skip_empty_prefix() {
+            let series = &to_series(&mock_families(), &[])[0];
+
+            let actual = to_line("", series, 1000);
+
+            assert!(actual.starts_with("mc_mined;"));
+        }
+    }
+
+    mod write {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_send_lines_over_tcp() {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let graphite = Graphite::new(&addr.to_string(), "minecraft.");
+
+            let receive = tokio::spawn(async move {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut received = String::new();
+                socket.read_to_string(&mut received).await.unwrap();
+
+                received
+            });
+            graphite.write(&mock_families()).await.unwrap();
+
+            let actual = receive.await.unwrap();
+
+            assert!(
+                actual.starts_with("minecraft.mc_mined;player=Alice_Doe;type=minecraft:stone 30 ")
+            );
+            assert!(actual.ends_with('\n'));
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use crate::sinks::series::{to_series, Series};
+use crate::sinks::udp::send_lines;
+use crate::Result;
+use prometheus::proto::MetricFamily;
+use reqwest::{header::AUTHORIZATION, Client};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+enum Transport {
+    Http {
+        client: Client,
+        url: String,
+        token: Option<String>,
+    },
+    Udp {
+        addr: String,
+    },
+}
+
+/// Writes metrics in InfluxDB line protocol, labels become tags and the value the field `value`
+pub struct InfluxDb {
+    transport: Transport,
+}
+
+impl InfluxDb {
+    /// `url` is either a full http write url, e.g. `http://influx:8086/write?db=minecraft`,
+    /// or `udp://influx:8089` for the UDP listener
+    pub fn new(url: &str, token: Option<String>) -> Result<Self> {
+        let transport = if url.starts_with("udp://") {
+            Transport::Udp {
+                addr: url
+                    .trim_start_matches("udp://")
+                    .trim_end_matches('/')
+                    .to_string(),
+            }
+        } else {
+            Transport::Http {
+                client: Client::builder().timeout(Duration::from_secs(10)).build()?,
+                url: String::from(url),
+                token,
+            }
+        };
+
+        Ok(Self { transport })
+    }
+
+    pub async fn write(&self, families: &[MetricFamily]) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let lines: Vec<String> = to_series(families, &[])
+            .iter()
+            .map(|series| to_line(series, timestamp))
+            .collect();
+
+        match &self.transport {
+            Transport::Http { client, url, token } => {
+                let mut request = client.post(url).body(lines.join("\n"));
+                if let Some(token) = token {
+                    request = request.header(AUTHORIZATION, format!("Token {}", token));
+                }
+
+                let response = request.send().await?;
+                if !response.status().is_success() {
+                    let status = response.status();
+                    let text = response.text().await.unwrap_or_default();
+                    Err(format!("InfluxDB answered {}: {}", status, text.trim()))?
+                }
+            }
+            Transport::Udp { addr } => {
+                send_lines(addr, &lines).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `mc_mined,player=Alice,type=minecraft:stone value=30 1589000000000000000`
+fn to_line(series: &Series, timestamp: u128) -> String {
+    let mut line = escape(series.name(), &[',', ' ']);
+
+    for (name, value) in series.tags() {
+        if value.is_empty() {
+            continue;
+        }
+
+        line.push(',');
+        line.push_str(&escape(name, &[',', '=', ' ']));
+        line.push('=');
+        line.push_str(&escape(value, &[',', '=', ' ']));
+    }
+
+    format!("{} value={} {}", line, series.value, timestamp)
+}
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::stub::stub_server;
+    use crate::sinks::udp::MAX_DATAGRAM;
+    use prometheus::{CounterVec, Opts, Registry};
+    use tokio::net::UdpSocket;
+
+    fn mock_families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let counter =
+            CounterVec::new(Opts::new("mc_mined", "mined help"), &["player", "type"]).unwrap();
+        counter
+            .with_label_values(&["Alice Doe", "minecraft:stone"])
+            .inc_by(30.0);
+        registry.register(Box::new(counter)).unwrap();
+
+        registry.gather()
+    }
+
+    mod to_line {
+        use super::*;
+
+        #[test]
+        fn should_use_labels_as_tags() {
+            let series = &to_series(&mock_families(), &[])[0];
+
+            let actual = to_line(series, 1000);
+            let expected = "mc_mined,player=Alice\\ Doe,type=minecraft:stone value=30 1000";
+
+            assert_eq!(actual, expected);
+        }
+    }
+
+    mod write {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_post_lines_over_http() {
+            let (addr, received) = stub_server(vec![204]);
+            let influx = InfluxDb::new(
+                &format!("http://{}/api/v2/write?bucket=minecraft", addr),
+                Some(String::from("secret")),
+            )
+            .unwrap();
+
+            influx.write(&mock_families()).await.unwrap();
+
+            let received = received.lock().unwrap();
+            let body = String::from_utf8_lossy(&received[0].body);
+
+            assert_eq!(received[0].path, "/api/v2/write");
+            assert_eq!(received[0].headers["authorization"], "Token secret");
+            assert!(body.starts_with("mc_mined,player=Alice\\ Doe"));
+        }
+
+        #[tokio::test]
+        async fn should_fail_on_rejected_writes() {
+            let (addr, _) = stub_server(vec![400]);
+            let influx = InfluxDb::new(&format!("http://{}/write", addr), None).unwrap();
+
+            assert!(influx.write(&mock_families()).await.is_err());
+        }
+
+        #[tokio::test]
+        async fn should_send_datagrams_over_udp() {
+            let mut listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let influx = InfluxDb::new(&format!("udp://{}", addr), None).unwrap();
+
+            influx.write(&mock_families()).await.unwrap();
+
+            let mut buf = [0; MAX_DATAGRAM];
+            let (len, _) = listener.recv_from(&mut buf).await.unwrap();
+            let actual = String::from_utf8_lossy(&buf[..len]);
+
+            assert!(actual.starts_with("mc_mined,player=Alice\\ Doe,type=minecraft:stone value=30"));
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::Result;
+use prometheus::proto::MetricFamily;
+
+pub use graphite::Graphite;
+pub use influxdb::InfluxDb;
+pub use mqtt::{Mqtt, MqttConfig};
+pub use pushgateway::Pushgateway;
+pub use remote_write::RemoteWrite;
+pub use statsd::StatsD;
+
+mod graphite;
+mod influxdb;
+mod mqtt;
+mod pushgateway;
+mod remote_write;
+mod series;
+mod statsd;
+#[cfg(test)]
+mod stub;
+mod udp;
+
+/// Outputs the game metrics are written to after every scrape
+pub enum Sink {
+    InfluxDb(InfluxDb),
+    Graphite(Graphite),
+}
+
+impl Sink {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sink::InfluxDb(_) => "InfluxDB",
+            Sink::Graphite(_) => "Graphite",
+        }
+    }
+
+    pub async fn write(&self, families: &[MetricFamily]) -> Result<()> {
+        match self {
+            Sink::InfluxDb(influxdb) => influxdb.write(families).await,
+            Sink::Graphite(graphite) => graphite.write(families).await,
+        }
+    }
+}
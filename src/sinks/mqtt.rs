@@ -0,0 +1,789 @@
+use crate::prometheus_handler::{Snapshot, NBT_GAUGES};
+use crate::Result;
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::{mpsc, oneshot, Mutex},
+    time::{self, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const KEEP_ALIVE_SECS: u16 = 60;
+/// Publishes queued for the connection task before `publish` waits
+const QUEUED_PUBLISHES: usize = 64;
+/// QoS 1 and 2 publishes sent without acknowledgement before the connection task waits
+const MAX_IN_FLIGHT: usize = 64;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const PUBREC: u8 = 0x50;
+const PUBREL: u8 = 0x62;
+const PUBCOMP: u8 = 0x70;
+const PINGREQ: u8 = 0xc0;
+const PINGRESP: u8 = 0xd0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    /// Broker address like `localhost:1883`
+    pub addr: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Used as second topic level, `minecraft/<world>/<player>/<metric>`
+    pub world: String,
+    pub qos: u8,
+    pub retain: bool,
+    /// Publish Home Assistant discovery configs for the nbt sensors of every player
+    pub home_assistant: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Message {
+    topic: String,
+    payload: String,
+    retain: bool,
+}
+
+/// A message for the connection task, answered once the broker acknowledged it
+struct Publish {
+    message: Message,
+    done: oneshot::Sender<Result<()>>,
+}
+
+/// Handle of a connection task, which owns the socket
+struct Connection {
+    publishes: mpsc::Sender<Publish>,
+    /// Players whose discovery configs were sent on this connection
+    discovered: HashSet<String>,
+}
+
+#[derive(Default)]
+struct MqttState {
+    connection: Option<Connection>,
+    /// Time a player was last published, keyed by uuid
+    published: HashMap<String, SystemTime>,
+    /// Snapshot of the last successful publish, changed stats are published relative to it
+    previous: Option<Arc<Snapshot>>,
+}
+
+/// Publishes nbt stats of updated players and changed stat values to an MQTT broker
+pub struct Mqtt {
+    config: MqttConfig,
+    state: Mutex<MqttState>,
+}
+
+impl Mqtt {
+    pub fn new(config: MqttConfig) -> Result<Self> {
+        if config.qos > 2 {
+            Err("MQTT QoS has to be 0, 1 or 2")?
+        }
+
+        Ok(Self {
+            config,
+            state: Mutex::new(MqttState::default()),
+        })
+    }
+
+    /// Publishes everything new since the last successful call, reconnecting if the connection
+    /// broke
+    pub async fn publish(&self, snapshot: &Arc<Snapshot>) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        let mut connection = match state.connection.take() {
+            Some(connection) => connection,
+            None => self.connect().await?,
+        };
+
+        let mut messages = vec![];
+        let mut published = vec![];
+
+        for (uuid, player) in snapshot.players.iter() {
+            let updated = snapshot.updated.get(uuid).copied();
+            if updated.is_some() && state.published.get(uuid).copied() == updated {
+                continue;
+            }
+
+            if self.config.home_assistant && !connection.discovered.contains(uuid) {
+                messages.extend(self.discovery_messages(&player.name, uuid));
+            }
+
+            for gauge in NBT_GAUGES.iter() {
+                messages.push(self.message(
+                    &[&player.name, metric_name(gauge.name)],
+                    ((gauge.value)(&player.nbt_stats)).to_string(),
+                ));
+            }
+
+            published.push((uuid.clone(), updated));
+        }
+
+        let changes = match &state.previous {
+            Some(previous) => snapshot.changes_since(previous),
+            None => vec![],
+        };
+        for change in changes {
+            let category = change.diff.category.to_string();
+            messages.push(self.message(
+                &[
+                    &change.player,
+                    strip_namespace(&category),
+                    strip_namespace(&change.diff.key),
+                ],
+                change.diff.new.to_string(),
+            ));
+        }
+
+        // On errors the connection is dropped, forcing a reconnect on the next publish
+        send_all(&mut connection, messages).await?;
+
+        for (uuid, updated) in published {
+            if self.config.home_assistant {
+                connection.discovered.insert(uuid.clone());
+            }
+            if let Some(updated) = updated {
+                state.published.insert(uuid, updated);
+            }
+        }
+        state.connection = Some(connection);
+        state.previous = Some(snapshot.clone());
+
+        Ok(())
+    }
+
+    async fn connect(&self) -> Result<Connection> {
+        let mut stream =
+            time::timeout(TIMEOUT, TcpStream::connect(self.config.addr.as_str())).await??;
+
+        let mut flags = 0x02; // Clean session
+        let mut payload = vec![];
+        write_string(&mut payload, &self.config.client_id);
+        if let Some(username) = &self.config.username {
+            flags |= 0x80;
+            write_string(&mut payload, username);
+        }
+        if let Some(password) = &self.config.password {
+            flags |= 0x40;
+            write_string(&mut payload, password);
+        }
+
+        let mut body = vec![];
+        write_string(&mut body, "MQTT");
+        body.push(4); // Protocol level 3.1.1
+        body.push(flags);
+        body.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+        body.extend(payload);
+        write_packet(&mut stream, CONNECT, &body).await?;
+
+        let (packet, body) = time::timeout(TIMEOUT, read_packet(&mut stream)).await??;
+        if packet & 0xf0 != CONNACK {
+            Err(format!("Expected MQTT CONNACK, got {:#x}", packet))?
+        }
+        match body.get(1) {
+            Some(0) => debug!("Connected to MQTT broker {}", self.config.addr),
+            Some(code) => Err(format!("MQTT broker refused connection with code {}", code))?,
+            None => Err(format!("Malformed MQTT packet {:#x}", packet))?,
+        }
+
+        let (publishes, receiver) = mpsc::channel(QUEUED_PUBLISHES);
+        tokio::spawn(run_connection(stream, self.config.qos, receiver));
+
+        Ok(Connection {
+            publishes,
+            discovered: HashSet::new(),
+        })
+    }
+
+    fn message(&self, levels: &[&str], payload: String) -> Message {
+        let mut topic = format!("minecraft/{}", topic_level(&self.config.world));
+        for level in levels {
+            topic.push('/');
+            topic.push_str(&topic_level(level));
+        }
+
+        Message {
+            topic,
+            payload,
+            retain: self.config.retain,
+        }
+    }
+
+    /// Home Assistant sensor configs, grouping all sensors of a player into one device
+    fn discovery_messages(&self, player: &str, uuid: &str) -> Vec<Message> {
+        let world = topic_level(&self.config.world);
+
+        NBT_GAUGES
+            .iter()
+            .map(|gauge| {
+                let metric = metric_name(gauge.name);
+                let object_id = format!("minecraft_{}_{}_{}", world, uuid, metric);
+                let config = json!({
+                    "name": format!("{} {}", player, metric.replace('_', " ")),
+                    "unique_id": object_id,
+                    "state_topic": self.message(&[player, metric], String::new()).topic,
+                    "state_class": "measurement",
+                    "device": {
+                        "identifiers": [format!("minecraft_{}_{}", world, uuid)],
+                        "name": format!("Minecraft {}", player),
+                        "manufacturer": "rs-minecraft-exporter",
+                    },
+                });
+
+                Message {
+                    topic: format!("homeassistant/sensor/{}/config", object_id),
+                    payload: config.to_string(),
+                    retain: true,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Queues all messages on the connection task and waits until the broker acknowledged them,
+/// all within a single timeout
+async fn send_all(connection: &mut Connection, messages: Vec<Message>) -> Result<()> {
+    let publishes = &mut connection.publishes;
+
+    time::timeout(TIMEOUT, async {
+        let mut acknowledged = vec![];
+
+        // Waits while the connection task has too many publishes in flight
+        for message in messages {
+            let (done, ack) = oneshot::channel();
+            publishes
+                .send(Publish { message, done })
+                .await
+                .map_err(|_| "MQTT connection closed")?;
+            acknowledged.push(ack);
+        }
+
+        for ack in acknowledged {
+            ack.await.map_err(|_| "MQTT connection closed")??;
+        }
+
+        Ok(())
+    })
+    .await
+    .map_err(|_| "MQTT broker did not acknowledge publishes in time")?
+}
+
+/// Owns the socket of a connection: writes publishes, matches acknowledgements
+/// to their packet id and keeps the connection alive with pings.
+/// Ends once the connection broke or its `Mqtt` is gone, failing all unacknowledged publishes.
+async fn run_connection(stream: TcpStream, qos: u8, mut publishes: mpsc::Receiver<Publish>) {
+    let (mut reader, mut writer) = io::split(stream);
+    let (mut packet_sender, mut packets) = mpsc::channel(QUEUED_PUBLISHES);
+
+    // Reading is not cancellation safe, so packets are read on their own task
+    tokio::spawn(async move {
+        while let Ok(packet) = read_packet(&mut reader).await {
+            if packet_sender.send(packet).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let interval = Duration::from_secs(KEEP_ALIVE_SECS as u64 / 2);
+    let mut keep_alive = time::interval_at(Instant::now() + interval, interval);
+    let mut awaiting_ping = false;
+    let mut next_id: u16 = 1;
+    let mut pending: HashMap<u16, oneshot::Sender<Result<()>>> = HashMap::new();
+
+    let result: Result<()> = async {
+        loop {
+            tokio::select! {
+                // Unacknowledged publishes are capped, which also leaves packet ids to choose from
+                publish = publishes.recv(), if pending.len() < MAX_IN_FLIGHT => match publish {
+                    Some(Publish { message, done }) => {
+                        while pending.contains_key(&next_id) {
+                            next_id = next_id.checked_add(1).unwrap_or(1);
+                        }
+                        let id = next_id;
+                        next_id = next_id.checked_add(1).unwrap_or(1);
+
+                        write_publish(&mut writer, qos, id, &message).await?;
+                        if qos == 0 {
+                            let _ = done.send(Ok(()));
+                        } else {
+                            pending.insert(id, done);
+                        }
+                    }
+                    None => return Ok(()),
+                },
+                packet = packets.recv() => match packet {
+                    Some((header, body)) => {
+                        let id = body.get(0..2).map(|id| u16::from_be_bytes([id[0], id[1]]));
+
+                        match (header & 0xf0, id) {
+                            (PUBACK, Some(id)) | (PUBCOMP, Some(id)) => match pending.remove(&id) {
+                                Some(done) => {
+                                    let _ = done.send(Ok(()));
+                                }
+                                None => debug!("Unexpected MQTT acknowledgement of {}", id),
+                            },
+                            (PUBREC, Some(id)) => {
+                                write_packet(&mut writer, PUBREL, &id.to_be_bytes()).await?
+                            }
+                            (PINGRESP, _) => awaiting_ping = false,
+                            (header, _) => trace!("Skipping MQTT packet {:#x}", header),
+                        }
+                    }
+                    None => Err("MQTT broker closed the connection")?,
+                },
+                _ = keep_alive.tick() => {
+                    if awaiting_ping {
+                        Err("MQTT broker did not answer ping")?
+                    }
+                    write_packet(&mut writer, PINGREQ, &[]).await?;
+                    awaiting_ping = true;
+                }
+            }
+        }
+    }
+    .await;
+
+    // Dropping `pending` fails everyone still waiting for an acknowledgement
+    if let Err(e) = result {
+        warn!("MQTT connection lost: {}", e);
+    }
+}
+
+async fn write_publish<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    qos: u8,
+    id: u16,
+    message: &Message,
+) -> Result<()> {
+    let mut body = vec![];
+    write_string(&mut body, &message.topic);
+    if qos > 0 {
+        body.extend_from_slice(&id.to_be_bytes());
+    }
+    body.extend_from_slice(message.payload.as_bytes());
+
+    let header = PUBLISH | qos << 1 | message.retain as u8;
+    write_packet(writer, header, &body).await
+}
+
+fn metric_name(gauge_name: &str) -> &str {
+    gauge_name.trim_start_matches("mc_")
+}
+
+fn strip_namespace(key: &str) -> &str {
+    key.trim_start_matches("minecraft:")
+}
+
+/// Wildcards and separators are not allowed inside a topic level
+fn topic_level(level: &str) -> String {
+    level
+        .chars()
+        .map(|c| match c {
+            '/' | '+' | '#' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+async fn write_packet<W: AsyncWrite + Unpin>(
+    stream: &mut W,
+    header: u8,
+    body: &[u8],
+) -> Result<()> {
+    let mut packet = vec![header];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+
+    time::timeout(TIMEOUT, stream.write_all(&packet)).await??;
+
+    Ok(())
+}
+
+async fn read_packet<R: AsyncRead + Unpin>(stream: &mut R) -> Result<(u8, Vec<u8>)> {
+    let header = stream.read_u8().await?;
+
+    let mut len = 0usize;
+    for shift in 0..4 {
+        let byte = stream.read_u8().await?;
+        len |= ((byte & 0x7f) as usize) << (7 * shift);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await?;
+
+    Ok((header, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_player;
+    use crate::player::Player;
+    use crate::prometheus_handler::StatCache;
+    use crate::stats::Stats;
+    use std::{net::SocketAddr, sync::Arc};
+    use tokio::net::TcpListener;
+
+    #[derive(Debug)]
+    struct Published {
+        topic: String,
+        payload: String,
+        retain: bool,
+        qos: u8,
+    }
+
+    /// Accepts a single client, acknowledges everything and records all publishes
+    async fn fake_broker() -> (SocketAddr, Arc<Mutex<Vec<Published>>>, Arc<Mutex<Vec<u8>>>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let published = Arc::new(Mutex::new(vec![]));
+        let connect = Arc::new(Mutex::new(vec![]));
+
+        let broker_published = published.clone();
+        let broker_connect = connect.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            while let Ok((header, body)) = read_packet(&mut stream).await {
+                match header & 0xf0 {
+                    CONNECT => {
+                        *broker_connect.lock().await = body;
+                        write_packet(&mut stream, CONNACK, &[0, 0]).await.unwrap();
+                    }
+                    PUBLISH => {
+                        let qos = header >> 1 & 0x03;
+                        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                        let topic = String::from_utf8_lossy(&body[2..2 + topic_len]).into_owned();
+                        let offset = if qos > 0 {
+                            2 + topic_len + 2
+                        } else {
+                            2 + topic_len
+                        };
+
+                        broker_published.lock().await.push(Published {
+                            payload: String::from_utf8_lossy(&body[offset..]).into_owned(),
+                            topic,
+                            retain: header & 0x01 == 1,
+                            qos,
+                        });
+
+                        let id = &body[2 + topic_len..offset];
+                        match qos {
+                            1 => write_packet(&mut stream, PUBACK, id).await.unwrap(),
+                            2 => write_packet(&mut stream, PUBREC, id).await.unwrap(),
+                            _ => {}
+                        }
+                    }
+                    0x60 => write_packet(&mut stream, PUBCOMP, &body).await.unwrap(),
+                    0xc0 => write_packet(&mut stream, 0xd0, &[]).await.unwrap(),
+                    _ => break,
+                }
+            }
+        });
+
+        (addr, published, connect)
+    }
+
+    /// Accepts a single client and answers its publishes only after `count` arrived,
+    /// in reverse order and after an acknowledgement of an unknown packet id
+    async fn reordering_broker(count: usize) -> SocketAddr {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut ids = vec![];
+
+            while let Ok((header, body)) = read_packet(&mut stream).await {
+                match header & 0xf0 {
+                    CONNECT => write_packet(&mut stream, CONNACK, &[0, 0]).await.unwrap(),
+                    PUBLISH => {
+                        let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                        ids.push(body[2 + topic_len..4 + topic_len].to_vec());
+
+                        if ids.len() == count {
+                            write_packet(&mut stream, PUBACK, &[0xff, 0xff])
+                                .await
+                                .unwrap();
+                            for id in ids.drain(..).rev() {
+                                write_packet(&mut stream, PUBACK, &id).await.unwrap();
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        addr
+    }
+
+    /// Accepts a single client and acknowledges publishes only once `MAX_IN_FLIGHT` are waiting
+    /// or no more arrive, returns the most publishes seen waiting at once
+    async fn slow_broker() -> (SocketAddr, Arc<Mutex<usize>>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let most_waiting = Arc::new(Mutex::new(0));
+
+        let broker_most_waiting = most_waiting.clone();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut ids = vec![];
+
+            loop {
+                let packet = time::timeout(Duration::from_millis(50), read_packet(&mut stream));
+                match packet.await {
+                    Ok(Ok((header, body))) => match header & 0xf0 {
+                        CONNECT => write_packet(&mut stream, CONNACK, &[0, 0]).await.unwrap(),
+                        PUBLISH => {
+                            let topic_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                            ids.push(body[2 + topic_len..4 + topic_len].to_vec());
+
+                            let mut most_waiting = broker_most_waiting.lock().await;
+                            *most_waiting = (*most_waiting).max(ids.len());
+                            if ids.len() < MAX_IN_FLIGHT {
+                                continue;
+                            }
+                        }
+                        _ => continue,
+                    },
+                    Ok(Err(_)) => break,
+                    Err(_) => {}
+                }
+
+                for id in ids.drain(..) {
+                    write_packet(&mut stream, PUBACK, &id).await.unwrap();
+                }
+            }
+        });
+
+        (addr, most_waiting)
+    }
+
+    fn mock_config(addr: SocketAddr) -> MqttConfig {
+        MqttConfig {
+            addr: addr.to_string(),
+            client_id: String::from("test"),
+            username: Some(String::from("user")),
+            password: Some(String::from("pass")),
+            world: String::from("world"),
+            qos: 1,
+            retain: true,
+            home_assistant: false,
+        }
+    }
+
+    fn mock_snapshot() -> Arc<Snapshot> {
+        let cache = StatCache::new().unwrap();
+        cache.update(vec![mock_player!(1)]);
+
+        cache.snapshot()
+    }
+
+    /// Player 1 with `count` mined blocks of as many kinds
+    fn mock_miner(count: usize, mined: u32) -> Player {
+        let blocks: Vec<String> = (0..count)
+            .map(|block| format!("\"minecraft:block_{}\": {}", block, mined))
+            .collect();
+        let stats = Stats::from(format!(
+            "{{ \"stats\": {{ \"minecraft:mined\": {{ {} }} }} }}",
+            blocks.join(", ")
+        ))
+        .unwrap();
+
+        mock_player!(1, stats)
+    }
+
+    mod topic_level {
+        use super::*;
+
+        #[test]
+        fn should_replace_wildcards() {
+            assert_eq!(topic_level("a/b+c#"), "a_b_c_");
+        }
+    }
+
+    mod publish {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_publish_nbt_and_changes() {
+            let (addr, published, connect) = fake_broker().await;
+            let mqtt = Mqtt::new(mock_config(addr)).unwrap();
+            let cache = StatCache::new().unwrap();
+
+            cache.update(vec![mock_miner(1, 1)]);
+            mqtt.publish(&cache.snapshot()).await.unwrap();
+            cache.update(vec![mock_miner(1, 2)]);
+            mqtt.publish(&cache.snapshot()).await.unwrap();
+
+            let published = published.lock().await;
+            let health = published
+                .iter()
+                .find(|p| p.topic == "minecraft/world/name-1/health")
+                .unwrap();
+            let stone = published
+                .iter()
+                .find(|p| p.topic == "minecraft/world/name-1/mined/block_0")
+                .unwrap();
+
+            assert_eq!(published.len(), 2 * NBT_GAUGES.len() + 1);
+            assert_eq!(health.payload, "10");
+            assert!(health.retain);
+            assert_eq!(health.qos, 1);
+            assert_eq!(stone.payload, "2");
+            // Username and password flags
+            assert_eq!(connect.lock().await[7] & 0xc0, 0xc0);
+        }
+
+        #[tokio::test]
+        async fn should_not_republish_unchanged_players() {
+            let (addr, published, _) = fake_broker().await;
+            let mqtt = Mqtt::new(mock_config(addr)).unwrap();
+            let snapshot = mock_snapshot();
+
+            mqtt.publish(&snapshot).await.unwrap();
+            mqtt.publish(&snapshot).await.unwrap();
+
+            assert_eq!(published.lock().await.len(), NBT_GAUGES.len());
+        }
+
+        #[tokio::test]
+        async fn should_publish_all_changes_of_large_scrapes() {
+            let (addr, published, _) = fake_broker().await;
+            let mqtt = Mqtt::new(mock_config(addr)).unwrap();
+            let cache = StatCache::new().unwrap();
+
+            cache.update(vec![mock_miner(2000, 1)]);
+            mqtt.publish(&cache.snapshot()).await.unwrap();
+            cache.update(vec![mock_miner(2000, 2)]);
+            mqtt.publish(&cache.snapshot()).await.unwrap();
+
+            let published = published.lock().await;
+            let changes = published.iter().filter(|p| p.topic.contains("/mined/"));
+
+            assert_eq!(changes.count(), 2000);
+        }
+
+        #[tokio::test]
+        async fn should_match_acknowledgements_to_packet_ids() {
+            let addr = reordering_broker(NBT_GAUGES.len()).await;
+            let mqtt = Mqtt::new(mock_config(addr)).unwrap();
+
+            assert!(mqtt.publish(&mock_snapshot()).await.is_ok());
+        }
+
+        #[tokio::test]
+        async fn should_limit_publishes_in_flight() {
+            let (addr, most_waiting) = slow_broker().await;
+            let mut config = mock_config(addr);
+            config.home_assistant = true;
+            let mqtt = Mqtt::new(config).unwrap();
+            let cache = StatCache::new().unwrap();
+            cache.update((1..=20).map(|id| mock_player!(id)).collect());
+
+            mqtt.publish(&cache.snapshot()).await.unwrap();
+
+            assert_eq!(*most_waiting.lock().await, MAX_IN_FLIGHT);
+        }
+
+        #[tokio::test]
+        async fn should_fail_when_broker_closes_connection() {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                read_packet(&mut stream).await.unwrap();
+                write_packet(&mut stream, CONNACK, &[0, 0]).await.unwrap();
+                read_packet(&mut stream).await.unwrap();
+            });
+            let mqtt = Mqtt::new(mock_config(addr)).unwrap();
+
+            assert!(mqtt.publish(&mock_snapshot()).await.is_err());
+        }
+
+        #[tokio::test]
+        async fn should_complete_qos_2_handshake() {
+            let (addr, published, _) = fake_broker().await;
+            let mut config = mock_config(addr);
+            config.qos = 2;
+            config.retain = false;
+            let mqtt = Mqtt::new(config).unwrap();
+
+            mqtt.publish(&mock_snapshot()).await.unwrap();
+
+            let published = published.lock().await;
+
+            assert_eq!(published.len(), NBT_GAUGES.len());
+            assert!(published.iter().all(|p| p.qos == 2 && !p.retain));
+        }
+
+        #[tokio::test]
+        async fn should_publish_home_assistant_discovery() {
+            let (addr, published, _) = fake_broker().await;
+            let mut config = mock_config(addr);
+            config.home_assistant = true;
+            let mqtt = Mqtt::new(config).unwrap();
+
+            mqtt.publish(&mock_snapshot()).await.unwrap();
+
+            let published = published.lock().await;
+            let discovery = published
+                .iter()
+                .find(|p| p.topic == "homeassistant/sensor/minecraft_world_1_health/config")
+                .unwrap();
+            let config: serde_json::Value = serde_json::from_str(&discovery.payload).unwrap();
+
+            assert_eq!(config["state_topic"], "minecraft/world/name-1/health");
+            assert_eq!(config["device"]["name"], "Minecraft name-1");
+            assert!(discovery.retain);
+        }
+
+        #[tokio::test]
+        async fn should_fail_without_broker() {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            drop(listener);
+            let mqtt = Mqtt::new(mock_config(addr)).unwrap();
+
+            assert!(mqtt.publish(&mock_snapshot()).await.is_err());
+        }
+    }
+
+    mod new {
+        use super::*;
+
+        #[test]
+        fn should_reject_invalid_qos() {
+            let mut config = mock_config(([127, 0, 0, 1], 1883).into());
+            config.qos = 3;
+
+            assert!(Mqtt::new(config).is_err());
+        }
+    }
+}
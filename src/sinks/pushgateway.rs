@@ -0,0 +1,204 @@
+use crate::Result;
+use prometheus::{proto::MetricFamily, Encoder, TextEncoder};
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode};
+use std::time::Duration;
+use tokio::time;
+
+/// Pushes gathered metrics to a Prometheus Pushgateway, replacing the previous push of the group
+pub struct Pushgateway {
+    client: Client,
+    url: String,
+    /// Attempts after the first failed push
+    pub retries: u32,
+    /// Wait before the first retry, doubled on every further retry
+    pub backoff: Duration,
+}
+
+impl Pushgateway {
+    pub fn new(base_url: &str, job: &str, instance: &str) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+        let url = format!(
+            "{}/metrics/{}/{}",
+            base_url.trim_end_matches('/'),
+            grouping_segment("job", job),
+            grouping_segment("instance", instance)
+        );
+
+        Ok(Self {
+            client,
+            url,
+            retries: 3,
+            backoff: Duration::from_secs(1),
+        })
+    }
+
+    /// Pushes `families` and retries with exponential backoff on connection and server errors
+    pub async fn push(&self, families: &[MetricFamily]) -> Result<()> {
+        let encoder = TextEncoder::new();
+        let mut body = vec![];
+        encoder.encode(families, &mut body)?;
+
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+
+        loop {
+            match self.try_push(&encoder, body.clone()).await {
+                Ok(()) => return Ok(()),
+                Err((e, retry)) if retry && attempt < self.retries => {
+                    attempt += 1;
+                    warn!(
+                        "Push to Pushgateway failed ({}), retrying in {:?}",
+                        e, backoff
+                    );
+
+                    time::delay_for(backoff).await;
+                    backoff *= 2;
+                }
+                Err((e, _)) => return Err(e),
+            }
+        }
+    }
+
+    /// Pushes once, errors are marked whether retrying could help
+    async fn try_push(
+        &self,
+        encoder: &TextEncoder,
+        body: Vec<u8>,
+    ) -> std::result::Result<(), (Box<dyn std::error::Error + Send + Sync>, bool)> {
+        let response = self
+            .client
+            .put(&self.url)
+            .header(CONTENT_TYPE, encoder.format_type())
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| (e.into(), true))?;
+
+        let status = response.status();
+        if status.is_success() {
+            trace!("Pushed metrics to {}", self.url);
+            return Ok(());
+        }
+
+        let text = response.text().await.unwrap_or_default();
+        let retry = status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+
+        Err((
+            format!("Pushgateway answered {}: {}", status, text.trim()).into(),
+            retry,
+        ))
+    }
+}
+
+/// Label values with slashes are base64 encoded, as path segments can not contain them
+fn grouping_segment(name: &str, value: &str) -> String {
+    if value.is_empty() {
+        format!("{}@base64/=", name)
+    } else if value.contains('/') {
+        format!(
+            "{}@base64/{}",
+            name,
+            base64::encode_config(value, base64::URL_SAFE)
+        )
+    } else {
+        let value =
+            percent_encoding::utf8_percent_encode(value, percent_encoding::NON_ALPHANUMERIC);
+
+        format!("{}/{}", name, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::stub::stub_server;
+    use hyper::Method;
+    use prometheus::{IntCounter, Registry};
+    use std::net::SocketAddr;
+
+    fn mock_families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let counter = IntCounter::new("mc_testo", "testo help").unwrap();
+        counter.inc();
+        registry.register(Box::new(counter)).unwrap();
+
+        registry.gather()
+    }
+
+    fn pushgateway(addr: SocketAddr) -> Pushgateway {
+        let mut pushgateway =
+            Pushgateway::new(&format!("http://{}/", addr), "minecraft", "world").unwrap();
+        pushgateway.backoff = Duration::from_millis(1);
+
+        pushgateway
+    }
+
+    mod grouping_segment {
+        use super::*;
+
+        #[test]
+        fn should_keep_plain_values() {
+            assert_eq!(grouping_segment("job", "minecraft"), "job/minecraft");
+        }
+
+        #[test]
+        fn should_encode_values_with_slashes() {
+            assert_eq!(
+                grouping_segment("instance", "srv/world"),
+                "instance@base64/c3J2L3dvcmxk"
+            );
+        }
+
+        #[test]
+        fn should_encode_empty_values() {
+            assert_eq!(grouping_segment("instance", ""), "instance@base64/=");
+        }
+    }
+
+    mod push {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_put_text_format_to_group() {
+            let (addr, received) = stub_server(vec![200]);
+
+            pushgateway(addr).push(&mock_families()).await.unwrap();
+
+            let received = received.lock().unwrap();
+            let actual = &received[0];
+
+            assert_eq!(actual.method, Method::PUT);
+            assert_eq!(actual.path, "/metrics/job/minecraft/instance/world");
+            assert!(String::from_utf8_lossy(&actual.body).contains("mc_testo 1"));
+        }
+
+        #[tokio::test]
+        async fn should_retry_server_errors() {
+            let (addr, received) = stub_server(vec![503, 500, 202]);
+
+            pushgateway(addr).push(&mock_families()).await.unwrap();
+
+            assert_eq!(received.lock().unwrap().len(), 3);
+        }
+
+        #[tokio::test]
+        async fn should_give_up_after_retries() {
+            let (addr, received) = stub_server(vec![500, 500, 500, 500, 500]);
+
+            let actual = pushgateway(addr).push(&mock_families()).await;
+
+            assert!(actual.is_err());
+            assert_eq!(received.lock().unwrap().len(), 4);
+        }
+
+        #[tokio::test]
+        async fn should_not_retry_client_errors() {
+            let (addr, received) = stub_server(vec![400, 200]);
+
+            let actual = pushgateway(addr).push(&mock_families()).await;
+
+            assert!(actual.is_err());
+            assert_eq!(received.lock().unwrap().len(), 1);
+        }
+    }
+}
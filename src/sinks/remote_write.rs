@@ -0,0 +1,358 @@
+use crate::sinks::series::{to_series, Series};
+use crate::Result;
+use prometheus::{proto::MetricFamily, IntCounter, IntGauge, Registry};
+use reqwest::{
+    header::{CONTENT_ENCODING, CONTENT_TYPE},
+    Client, StatusCode,
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Mutex;
+
+/// Samples of a single scrape, sent in write requests of at most `max_samples_per_send` samples
+#[derive(Debug, Clone, PartialEq)]
+struct Batch {
+    timestamp_ms: i64,
+    series: Vec<Series>,
+}
+
+/// Ships samples to a Prometheus remote-write endpoint like Mimir, Cortex or Thanos.
+/// Batches that could not be sent are queued and retried in order on the next scrape,
+/// continuing with the samples not sent yet.
+pub struct RemoteWrite {
+    client: Client,
+    url: String,
+    tenant: Option<String>,
+    external_labels: Vec<(String, String)>,
+    queue: Mutex<VecDeque<Batch>>,
+    /// Oldest batches are dropped once more are waiting
+    pub max_queued: usize,
+    /// Large scrapes are split to stay below message size limits of the endpoint
+    pub max_samples_per_send: usize,
+    sent_samples: IntCounter,
+    dropped_samples: IntCounter,
+    failures: IntCounter,
+    queued_batches: IntGauge,
+}
+
+impl RemoteWrite {
+    pub fn new(
+        url: &str,
+        tenant: Option<String>,
+        external_labels: Vec<(String, String)>,
+    ) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(30)).build()?;
+
+        Ok(Self {
+            client,
+            url: String::from(url),
+            tenant,
+            external_labels,
+            queue: Mutex::new(VecDeque::new()),
+            max_queued: 120,
+            max_samples_per_send: 2000,
+            sent_samples: IntCounter::new(
+                "mc_exporter_remote_write_sent_samples_total",
+                "samples successfully sent via remote write",
+            )?,
+            dropped_samples: IntCounter::new(
+                "mc_exporter_remote_write_dropped_samples_total",
+                "samples dropped because the queue was full or the endpoint rejected them",
+            )?,
+            failures: IntCounter::new(
+                "mc_exporter_remote_write_failures_total",
+                "failed remote write requests",
+            )?,
+            queued_batches: IntGauge::new(
+                "mc_exporter_remote_write_queued_batches",
+                "scrapes waiting to be sent via remote write",
+            )?,
+        })
+    }
+
+    pub fn register(&self, registry: &Registry) -> Result<()> {
+        registry.register(Box::new(self.sent_samples.clone()))?;
+        registry.register(Box::new(self.dropped_samples.clone()))?;
+        registry.register(Box::new(self.failures.clone()))?;
+        registry.register(Box::new(self.queued_batches.clone()))?;
+
+        Ok(())
+    }
+
+    /// Queues the current values of `families` and sends everything queued
+    pub async fn send(&self, families: &[MetricFamily]) {
+        let mut queue = self.queue.lock().await;
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_millis() as i64)
+            .unwrap_or_default();
+        queue.push_back(Batch {
+            timestamp_ms,
+            series: to_series(families, &self.external_labels),
+        });
+
+        while queue.len() > self.max_queued {
+            if let Some(dropped) = queue.pop_front() {
+                warn!("Remote write queue full, dropping oldest samples");
+                self.dropped_samples.inc_by(dropped.series.len() as i64);
+            }
+        }
+
+        while let Some(batch) = queue.front_mut() {
+            let count = batch.series.len().min(self.max_samples_per_send.max(1));
+
+            match self.write(batch.timestamp_ms, &batch.series[..count]).await {
+                Ok(()) => self.sent_samples.inc_by(count as i64),
+                Err((e, true)) => {
+                    self.failures.inc();
+                    warn!("Remote write failed, retrying next scrape: {}", e);
+                    break;
+                }
+                Err((e, false)) => {
+                    self.failures.inc();
+                    self.dropped_samples.inc_by(count as i64);
+                    error!("Remote write rejected, dropping samples: {}", e);
+                }
+            }
+
+            batch.series.drain(..count);
+            if batch.series.is_empty() {
+                queue.pop_front();
+            }
+        }
+
+        self.queued_batches.set(queue.len() as i64);
+    }
+
+    /// Sends a single write request, errors are marked whether retrying could help
+    async fn write(
+        &self,
+        timestamp_ms: i64,
+        series: &[Series],
+    ) -> std::result::Result<(), (Box<dyn std::error::Error + Send + Sync>, bool)> {
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&encode_write_request(timestamp_ms, series))
+            .map_err(|e| (e.into(), false))?;
+
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/x-protobuf")
+            .header(CONTENT_ENCODING, "snappy")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .body(body);
+        if let Some(tenant) = &self.tenant {
+            request = request.header("X-Scope-OrgID", tenant.as_str());
+        }
+
+        let response = request.send().await.map_err(|e| (e.into(), true))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let text = response.text().await.unwrap_or_default();
+        let retry = status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+
+        Err((
+            format!("endpoint answered {}: {}", status, text.trim()).into(),
+            retry,
+        ))
+    }
+}
+
+/// Encodes a `prometheus.WriteRequest` protobuf message with one sample per series
+fn encode_write_request(timestamp_ms: i64, series: &[Series]) -> Vec<u8> {
+    let mut request = vec![];
+
+    for series in series {
+        let mut time_series = vec![];
+
+        for (name, value) in &series.labels {
+            let mut label = vec![];
+            write_bytes(&mut label, 1, name.as_bytes());
+            write_bytes(&mut label, 2, value.as_bytes());
+            write_bytes(&mut time_series, 1, &label);
+        }
+
+        let mut sample = vec![];
+        // Field 1, fixed 64 bit double
+        sample.push(0x09);
+        sample.extend_from_slice(&series.value.to_le_bytes());
+        // Field 2, varint timestamp
+        sample.push(0x10);
+        write_varint(&mut sample, timestamp_ms as u64);
+        write_bytes(&mut time_series, 2, &sample);
+
+        write_bytes(&mut request, 1, &time_series);
+    }
+
+    request
+}
+
+/// Writes a length delimited field
+fn write_bytes(buf: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    buf.push(field << 3 | 2);
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sinks::stub::stub_server;
+    use prometheus::{GaugeVec, Opts};
+
+    fn mock_families() -> Vec<MetricFamily> {
+        mock_players(1)
+    }
+
+    fn mock_players(count: usize) -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(Opts::new("mc_health", "health help"), &["player"]).unwrap();
+        for id in 1..=count {
+            gauge
+                .with_label_values(&[&format!("name-{}", id)])
+                .set(10.0);
+        }
+        registry.register(Box::new(gauge)).unwrap();
+
+        registry.gather()
+    }
+
+    fn remote_write(url: &str) -> RemoteWrite {
+        RemoteWrite::new(
+            url,
+            Some(String::from("tenant-1")),
+            vec![(String::from("job"), String::from("minecraft"))],
+        )
+        .unwrap()
+    }
+
+    mod encode_write_request {
+        use super::*;
+
+        #[test]
+        fn should_encode_protobuf() {
+            let series = vec![Series {
+                labels: vec![(String::from("a"), String::from("b"))],
+                value: 1.0,
+            }];
+
+            let actual = encode_write_request(300, &series);
+            let expected = vec![
+                0x0a, 0x16, // timeseries
+                0x0a, 0x06, 0x0a, 0x01, b'a', 0x12, 0x01, b'b', // label
+                0x12, 0x0c, 0x09, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f, 0x10, 0xac, 0x02, // sample
+            ];
+
+            assert_eq!(actual, expected);
+        }
+    }
+
+    mod send {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_post_snappy_protobuf() {
+            let (addr, received) = stub_server(vec![204]);
+            let remote_write = remote_write(&format!("http://{}/api/v1/push", addr));
+
+            remote_write.send(&mock_families()).await;
+
+            let received = received.lock().unwrap();
+            let actual = &received[0];
+            let decompressed = snap::raw::Decoder::new()
+                .decompress_vec(&actual.body)
+                .unwrap();
+
+            assert_eq!(actual.path, "/api/v1/push");
+            assert_eq!(actual.headers["content-encoding"], "snappy");
+            assert_eq!(actual.headers["x-scope-orgid"], "tenant-1");
+            assert!(String::from_utf8_lossy(&decompressed).contains("mc_health"));
+            assert_eq!(remote_write.sent_samples.get(), 1);
+        }
+
+        #[tokio::test]
+        async fn should_retry_queued_batches_in_order() {
+            let (addr, received) = stub_server(vec![503, 200, 200]);
+            let remote_write = remote_write(&format!("http://{}/", addr));
+
+            remote_write.send(&mock_families()).await;
+            assert_eq!(remote_write.queued_batches.get(), 1);
+
+            remote_write.send(&mock_families()).await;
+
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 3);
+            assert_eq!(received[0].body, received[1].body);
+            assert_eq!(remote_write.queued_batches.get(), 0);
+            assert_eq!(remote_write.sent_samples.get(), 2);
+            assert_eq!(remote_write.failures.get(), 1);
+        }
+
+        #[tokio::test]
+        async fn should_drop_oldest_when_full() {
+            let (addr, _) = stub_server(vec![500, 500]);
+            let mut remote_write = remote_write(&format!("http://{}/", addr));
+            remote_write.max_queued = 1;
+
+            remote_write.send(&mock_families()).await;
+            remote_write.send(&mock_families()).await;
+
+            assert_eq!(remote_write.queued_batches.get(), 1);
+            assert_eq!(remote_write.dropped_samples.get(), 1);
+        }
+
+        #[tokio::test]
+        async fn should_split_large_scrapes() {
+            let (addr, received) = stub_server(vec![200, 200, 200]);
+            let mut remote_write = remote_write(&format!("http://{}/", addr));
+            remote_write.max_samples_per_send = 2;
+
+            remote_write.send(&mock_players(5)).await;
+
+            assert_eq!(received.lock().unwrap().len(), 3);
+            assert_eq!(remote_write.sent_samples.get(), 5);
+        }
+
+        #[tokio::test]
+        async fn should_resume_split_scrapes() {
+            let (addr, received) = stub_server(vec![200, 503, 200, 200]);
+            let mut remote_write = remote_write(&format!("http://{}/", addr));
+            remote_write.max_samples_per_send = 2;
+
+            remote_write.send(&mock_players(3)).await;
+            assert_eq!(remote_write.sent_samples.get(), 2);
+
+            remote_write.send(&mock_players(1)).await;
+
+            let received = received.lock().unwrap();
+            assert_eq!(received[1].body, received[2].body);
+            assert_eq!(remote_write.sent_samples.get(), 4);
+            assert_eq!(remote_write.queued_batches.get(), 0);
+        }
+
+        #[tokio::test]
+        async fn should_drop_rejected_batches() {
+            let (addr, _) = stub_server(vec![400]);
+            let remote_write = remote_write(&format!("http://{}/", addr));
+
+            remote_write.send(&mock_families()).await;
+
+            assert_eq!(remote_write.queued_batches.get(), 0);
+            assert_eq!(remote_write.dropped_samples.get(), 1);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use prometheus::proto::{MetricFamily, MetricType};
+
+/// Labels sorted by name, including `__name__`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+impl Series {
+    pub fn name(&self) -> &str {
+        self.labels
+            .iter()
+            .find(|(name, _)| name == "__name__")
+            .map_or("", |(_, value)| value.as_str())
+    }
+
+    /// All labels except the metric name
+    pub fn tags(&self) -> impl Iterator<Item = &(String, String)> {
+        self.labels.iter().filter(|(name, _)| name != "__name__")
+    }
+}
+
+/// Flattens counters, gauges and untyped metrics into series with sorted labels.
+/// Histograms become `_bucket` series labeled with `le`, `_sum` and `_count` like in the text
+/// format.
+pub fn to_series(families: &[MetricFamily], external_labels: &[(String, String)]) -> Vec<Series> {
+    let mut series = vec![];
+
+    for family in families {
+        let name = family.get_name();
+
+        for metric in family.get_metric() {
+            // Name suffix, additional label and value of every sample of the metric
+            let samples: Vec<(&str, Option<String>, f64)> = match family.get_field_type() {
+                MetricType::COUNTER => vec![("", None, metric.get_counter().get_value())],
+                MetricType::GAUGE => vec![("", None, metric.get_gauge().get_value())],
+                MetricType::UNTYPED => vec![("", None, metric.get_untyped().get_value())],
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let mut samples: Vec<_> = histogram
+                        .get_bucket()
+                        .iter()
+                        .map(|bucket| {
+                            (
+                                "_bucket",
+                                Some(bucket.get_upper_bound().to_string()),
+                                bucket.get_cumulative_count() as f64,
+                            )
+                        })
+                        .collect();
+                    let count = histogram.get_sample_count() as f64;
+                    samples.push(("_bucket", Some(String::from("+Inf")), count));
+                    samples.push(("_sum", None, histogram.get_sample_sum()));
+                    samples.push(("_count", None, count));
+
+                    samples
+                }
+                _ => {
+                    debug!("Skipping {} for output sinks", name);
+                    continue;
+                }
+            };
+
+            for (suffix, le, value) in samples {
+                let mut labels = vec![(String::from("__name__"), format!("{}{}", name, suffix))];
+                labels.extend(
+                    metric
+                        .get_label()
+                        .iter()
+                        .map(|label| (label.get_name().to_string(), label.get_value().to_string())),
+                );
+                if let Some(le) = le {
+                    labels.push((String::from("le"), le));
+                }
+                for (name, value) in external_labels {
+                    if labels.iter().all(|(existing, _)| existing != name) {
+                        labels.push((name.clone(), value.clone()));
+                    }
+                }
+                labels.sort();
+
+                series.push(Series { labels, value });
+            }
+        }
+    }
+
+    series
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{GaugeVec, Histogram, HistogramOpts, Opts, Registry};
+
+    fn mock_families() -> Vec<MetricFamily> {
+        let registry = Registry::new();
+        let gauge = GaugeVec::new(Opts::new("mc_health", "health help"), &["player"]).unwrap();
+        gauge.with_label_values(&["name-1"]).set(10.0);
+        registry.register(Box::new(gauge)).unwrap();
+
+        registry.gather()
+    }
+
+    mod to_series {
+        use super::*;
+
+        #[test]
+        fn should_sort_labels_with_name_and_external_labels() {
+            let actual = to_series(
+                &mock_families(),
+                &[(String::from("instance"), String::from("world"))],
+            );
+
+            let expected = vec![Series {
+                labels: vec![
+                    (String::from("__name__"), String::from("mc_health")),
+                    (String::from("instance"), String::from("world")),
+                    (String::from("player"), String::from("name-1")),
+                ],
+                value: 10.0,
+            }];
+
+            assert_eq!(actual, expected);
+        }
+
+        #[test]
+        fn should_expand_histograms() {
+            let registry = Registry::new();
+            let histogram = Histogram::with_opts(
+                HistogramOpts::new("mc_session_seconds", "help").buckets(vec![60.0, 300.0]),
+            )
+            .unwrap();
+            histogram.observe(30.0);
+            histogram.observe(120.0);
+            registry.register(Box::new(histogram)).unwrap();
+
+            let actual: Vec<_> = to_series(&registry.gather(), &[])
+                .into_iter()
+                .map(|series| (series.labels, series.value))
+                .collect();
+
+            let bucket = |le: &str| {
+                vec![
+                    (
+                        String::from("__name__"),
+                        String::from("mc_session_seconds_bucket"),
+                    ),
+                    (String::from("le"), String::from(le)),
+                ]
+            };
+            let name = |name: &str| vec![(String::from("__name__"), String::from(name))];
+            let expected = vec![
+                (bucket("60"), 1.0),
+                (bucket("300"), 2.0),
+                (bucket("+Inf"), 2.0),
+                (name("mc_session_seconds_sum"), 150.0),
+                (name("mc_session_seconds_count"), 2.0),
+            ];
+
+            assert_eq!(actual, expected);
+        }
+
+        #[test]
+        fn should_not_override_metric_labels() {
+            let actual = to_series(
+                &mock_families(),
+                &[(String::from("player"), String::from("other"))],
+            );
+
+            assert_eq!(actual[0].labels.len(), 2);
+            assert_eq!(actual[0].labels[1].1, "name-1");
+        }
+    }
+
+    mod series {
+        use super::*;
+
+        #[test]
+        fn should_split_name_and_tags() {
+            let actual = &to_series(&mock_families(), &[])[0];
+
+            assert_eq!(actual.name(), "mc_health");
+            assert_eq!(
+                actual.tags().collect::<Vec<_>>(),
+                vec![&(String::from("player"), String::from("name-1"))]
+            );
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::prometheus_handler::{Snapshot, StatChange, NBT_GAUGES};
+use crate::sinks::udp::send_lines;
+use crate::Result;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Sends nbt stats as gauges and stat increases as counters to a StatsD server over UDP
+pub struct StatsD {
+    addr: String,
+    prefix: String,
+    /// Use DogStatsD tags for player and item instead of putting them into the metric name
+    tags: bool,
+    /// Snapshot of the last successful send, counters are sent as increases since then
+    previous: Mutex<Option<Arc<Snapshot>>>,
+}
+
+impl StatsD {
+    pub fn new(addr: &str, prefix: &str, tags: bool) -> Self {
+        Self {
+            addr: String::from(addr),
+            prefix: prefix.trim_end_matches('.').to_string(),
+            tags,
+            previous: Mutex::new(None),
+        }
+    }
+
+    pub async fn send(&self, snapshot: &Arc<Snapshot>) -> Result<()> {
+        let mut previous = self.previous.lock().await;
+
+        let changes = match &*previous {
+            Some(previous) => snapshot.changes_since(previous),
+            None => vec![],
+        };
+        let lines = self.lines(snapshot, &changes);
+
+        if !lines.is_empty() {
+            send_lines(&self.addr, &lines).await?;
+        }
+        *previous = Some(snapshot.clone());
+
+        Ok(())
+    }
+
+    fn lines(&self, snapshot: &Snapshot, changes: &[StatChange]) -> Vec<String> {
+        let mut lines = vec![];
+
+        for player in snapshot.players.values() {
+            for gauge in NBT_GAUGES.iter() {
+                let value = (gauge.value)(&player.nbt_stats);
+                let metric = gauge.name.trim_start_matches("mc_");
+
+                lines.push(self.line(&player.name, &[metric], None, value, "g"));
+            }
+        }
+
+        for change in changes {
+            // Decreases are counter resets, the next increase is counted again
+            let delta = change.diff.new - change.diff.old.unwrap_or(0.0);
+            if delta <= 0.0 {
+                continue;
+            }
+
+            let category = change.diff.category.to_string();
+            let category = category.trim_start_matches("minecraft:");
+            let item = change.diff.key.trim_start_matches("minecraft:");
+
+            lines.push(self.line(&change.player, &[category], Some(item), delta, "c"));
+        }
+
+        lines
+    }
+
+    /// `minecraft.mined:2|c|#player:Alice,item:stone` with tags,
+    /// `minecraft.Alice.mined.stone:2|c` without
+    fn line(
+        &self,
+        player: &str,
+        metric: &[&str],
+        item: Option<&str>,
+        value: f64,
+        kind: &str,
+    ) -> String {
+        let mut name: Vec<String> = vec![];
+        if !self.prefix.is_empty() {
+            name.push(self.prefix.clone());
+        }
+
+        if self.tags {
+            name.extend(metric.iter().map(|m| sanitize(m)));
+
+            let mut tags = format!("player:{}", sanitize_tag(player));
+            if let Some(item) = item {
+                tags.push_str(&format!(",item:{}", sanitize_tag(item)));
+            }
+
+            format!("{}:{}|{}|#{}", name.join("."), value, kind, tags)
+        } else {
+            name.push(sanitize(player));
+            name.extend(metric.iter().map(|m| sanitize(m)));
+            if let Some(item) = item {
+                name.push(sanitize(item));
+            }
+
+            format!("{}:{}|{}", name.join("."), value, kind)
+        }
+    }
+}
+
+/// Replaces characters with a meaning in metric names
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | '#' | ',' | '.' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Tag values may contain colons and dots, but not tag or field separators
+fn sanitize_tag(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '|' | '@' | '#' | ',' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock_player;
+    use crate::player::Player;
+    use crate::prometheus_handler::StatCache;
+    use crate::stats::{StatCategory, StatDiff, Stats};
+    use tokio::net::UdpSocket;
+
+    fn mock_snapshot() -> Arc<Snapshot> {
+        let cache = StatCache::new().unwrap();
+        cache.update(vec![mock_player!(1)]);
+
+        cache.snapshot()
+    }
+
+    fn mock_change(old: Option<f64>, new: f64) -> StatChange {
+        StatChange {
+            player: String::from("Alice Doe"),
+            diff: StatDiff {
+                category: StatCategory::Mined,
+                key: String::from("minecraft:diamond_ore"),
+                old,
+                new,
+            },
+        }
+    }
+
+    mod lines {
+        use super::*;
+
+        #[test]
+        fn should_use_dogstatsd_tags() {
+            let statsd = StatsD::new("localhost:8125", "minecraft", true);
+
+            let actual = statsd.lines(&mock_snapshot(), &[mock_change(Some(3.0), 5.0)]);
+
+            assert!(actual.contains(&String::from("minecraft.health:10|g|#player:name-1")));
+            assert!(actual.contains(&String::from(
+                "minecraft.mined:2|c|#player:Alice_Doe,item:diamond_ore"
+            )));
+        }
+
+        #[test]
+        fn should_put_tags_into_name_without_dogstatsd() {
+            let statsd = StatsD::new("localhost:8125", "minecraft.", false);
+
+            let actual = statsd.lines(&mock_snapshot(), &[mock_change(None, 1.0)]);
+
+            assert!(actual.contains(&String::from("minecraft.name-1.health:10|g")));
+            assert!(actual.contains(&String::from("minecraft.Alice_Doe.mined.diamond_ore:1|c")));
+        }
+
+        #[test]
+        fn should_skip_counter_resets() {
+            let statsd = StatsD::new("localhost:8125", "minecraft", true);
+
+            let actual = statsd.lines(&mock_snapshot(), &[mock_change(Some(5.0), 1.0)]);
+
+            assert!(actual.iter().all(|line| !line.contains("|c")));
+        }
+    }
+
+    mod send {
+        use super::*;
+
+        #[tokio::test]
+        async fn should_send_datagram() {
+            let mut listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let statsd = StatsD::new(&addr.to_string(), "minecraft", true);
+
+            statsd.send(&mock_snapshot()).await.unwrap();
+
+            let mut buf = [0; 1500];
+            let (len, _) = listener.recv_from(&mut buf).await.unwrap();
+            let actual = String::from_utf8_lossy(&buf[..len]);
+
+            assert!(actual.contains("minecraft.food_level:10|g|#player:name-1"));
+        }
+
+        #[tokio::test]
+        async fn should_count_increases_since_previous_send() {
+            let mut listener = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let statsd = StatsD::new(&addr.to_string(), "minecraft", true);
+            let cache = StatCache::new().unwrap();
+            let mined = |count: u32| {
+                let stats = Stats::from(format!(
+                    "{{ \"stats\": {{ \"minecraft:mined\": {{ \"minecraft:stone\": {} }} }} }}",
+                    count
+                ))
+                .unwrap();
+                mock_player!(1, stats)
+            };
+
+            cache.update(vec![mined(3)]);
+            statsd.send(&cache.snapshot()).await.unwrap();
+            // Parsed twice between sends, the increase is still counted once
+            cache.update(vec![mined(4)]);
+            cache.update(vec![mined(8)]);
+            statsd.send(&cache.snapshot()).await.unwrap();
+
+            let mut buf = [0; 1500];
+            let mut received = String::new();
+            for _ in 0..2 {
+                let (len, _) = listener.recv_from(&mut buf).await.unwrap();
+                received.push_str(&String::from_utf8_lossy(&buf[..len]));
+            }
+
+            assert!(received.contains("minecraft.mined:5|c|#player:name-1,item:stone"));
+        }
+    }
+}
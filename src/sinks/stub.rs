@@ -0,0 +1,62 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug)]
+pub struct StubRequest {
+    pub method: Method,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+pub type Received = Arc<Mutex<Vec<StubRequest>>>;
+
+/// Local http server answering with the given statuses in order and recording every request
+pub fn stub_server(statuses: Vec<u16>) -> (SocketAddr, Received) {
+    let received: Received = Arc::new(Mutex::new(vec![]));
+    let statuses = Arc::new(Mutex::new(statuses));
+
+    let server_received = received.clone();
+    let make_svc = make_service_fn(move |_| {
+        let received = server_received.clone();
+        let statuses = statuses.clone();
+
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                let received = received.clone();
+                let statuses = statuses.clone();
+
+                async move {
+                    let method = req.method().clone();
+                    let path = req.uri().path().to_string();
+                    let headers = req.headers().clone();
+                    let body = hyper::body::to_bytes(req.into_body()).await?.to_vec();
+                    received.lock().unwrap().push(StubRequest {
+                        method,
+                        path,
+                        headers,
+                        body,
+                    });
+
+                    let status = statuses.lock().unwrap().remove(0);
+                    Ok::<_, hyper::Error>(
+                        Response::builder()
+                            .status(status)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+
+    (addr, received)
+}
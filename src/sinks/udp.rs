@@ -0,0 +1,62 @@
+use crate::Result;
+use std::net::SocketAddr;
+use tokio::net::{lookup_host, UdpSocket};
+
+/// Lines are packed into datagrams of at most this size to avoid fragmentation
+pub const MAX_DATAGRAM: usize = 1400;
+
+/// Sends newline separated `lines` in as few datagrams as possible
+pub async fn send_lines(addr: &str, lines: &[String]) -> Result<()> {
+    let target: SocketAddr = lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| format!("Could not resolve {}", addr))?;
+    let bind: SocketAddr = if target.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let mut socket = UdpSocket::bind(bind).await?;
+
+    for datagram in pack_datagrams(lines) {
+        socket.send_to(datagram.as_bytes(), &target).await?;
+    }
+
+    Ok(())
+}
+
+fn pack_datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams: Vec<String> = vec![];
+
+    for line in lines {
+        match datagrams.last_mut() {
+            Some(datagram) if datagram.len() + line.len() < MAX_DATAGRAM => {
+                datagram.push('\n');
+                datagram.push_str(line);
+            }
+            _ => datagrams.push(line.clone()),
+        }
+    }
+
+    datagrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod pack_datagrams {
+        use super::*;
+
+        #[test]
+        fn should_split_large_batches() {
+            let lines: Vec<String> = (0..100).map(|_| "x".repeat(100)).collect();
+
+            let actual = pack_datagrams(&lines);
+
+            assert!(actual.len() > 1);
+            assert!(actual.iter().all(|datagram| datagram.len() < MAX_DATAGRAM));
+            assert_eq!(actual.join("\n").lines().count(), 100);
+        }
+    }
+}
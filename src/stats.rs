@@ -1,8 +1,9 @@
-use serde::Deserialize;
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Result;
 use serde_json::{Map, Value};
 use std::cmp::Eq;
-use std::fmt::Display;
+use std::fmt::{self, Display};
 
 pub const STAT_CATEGORIES: [StatCategory; 9] = [
     StatCategory::Mined,
@@ -17,6 +18,7 @@ pub const STAT_CATEGORIES: [StatCategory; 9] = [
 ];
 
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+#[cfg_attr(feature = "graphql", derive(juniper::GraphQLEnum))]
 pub enum StatCategory {
     Mined,
     Crafted,
@@ -29,6 +31,19 @@ pub enum StatCategory {
     Killed,
 }
 
+impl StatCategory {
+    /// Finds a category by its name, with or without the `minecraft:` prefix
+    pub fn from_name(name: &str) -> Option<StatCategory> {
+        STAT_CATEGORIES
+            .iter()
+            .find(|category| {
+                let full = category.to_string();
+                full == name || full[10..] == *name
+            })
+            .copied()
+    }
+}
+
 impl Display for StatCategory {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let name = match self {
@@ -47,7 +62,23 @@ impl Display for StatCategory {
     }
 }
 
-#[derive(Debug, Deserialize)]
+impl Serialize for StatCategory {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// A single stat that changed between two parses of a player
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatDiff {
+    pub category: StatCategory,
+    pub key: String,
+    /// Missing for stats that did not exist before
+    pub old: Option<f64>,
+    pub new: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Stats {
     stats: Value,
 }
@@ -62,6 +93,58 @@ impl Stats {
     pub fn get_stat(&self, category: &StatCategory) -> Option<&Map<String, Value>> {
         self.stats[category.to_string()].as_object()
     }
+
+    /// Whether any stat is lower than in `previous` or disappeared,
+    /// which happens on world resets or restored backups
+    pub fn has_decreased_since(&self, previous: &Stats) -> bool {
+        STAT_CATEGORIES.iter().any(|category| {
+            let current = self.get_stat(category);
+
+            previous.get_stat(category).map_or(false, |previous| {
+                previous.iter().any(|(key, old)| {
+                    let new = current.and_then(|c| c.get(key)).and_then(|v| v.as_f64());
+
+                    match (old.as_f64(), new) {
+                        (Some(old), Some(new)) => new < old,
+                        (Some(_), None) => true,
+                        _ => false,
+                    }
+                })
+            })
+        })
+    }
+
+    /// All numeric stats that differ from `previous`
+    pub fn changes_since(&self, previous: &Stats) -> Vec<StatDiff> {
+        let mut changes = vec![];
+
+        for category in STAT_CATEGORIES.iter() {
+            let stats = match self.get_stat(category) {
+                Some(stats) => stats,
+                None => continue,
+            };
+            let previous = previous.get_stat(category);
+
+            for (key, value) in stats.iter() {
+                let new = match value.as_f64() {
+                    Some(new) => new,
+                    None => continue,
+                };
+                let old = previous.and_then(|p| p.get(key)).and_then(|v| v.as_f64());
+
+                if old != Some(new) {
+                    changes.push(StatDiff {
+                        category: *category,
+                        key: key.clone(),
+                        old,
+                        new,
+                    });
+                }
+            }
+        }
+
+        changes
+    }
 }
 
 #[macro_export]
@@ -78,7 +161,24 @@ macro_rules! mock_stats {
     };
 }
 
-#[derive(Debug, Deserialize)]
+#[macro_export]
+macro_rules! mock_custom_stats {
+    () => {
+        crate::stats::Stats::from(String::from(
+            "{
+               \"stats\": {
+                   \"minecraft:custom\": {
+                       \"minecraft:testo\": 42
+                   }
+               }
+            }",
+        ))
+        .unwrap()
+    };
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "graphql", derive(juniper::GraphQLObject))]
 pub struct NbtStats {
     #[serde(alias = "XpTotal")]
     pub xp_total: f64,
@@ -90,6 +190,48 @@ pub struct NbtStats {
     pub health: f64,
     #[serde(alias = "foodLevel")]
     pub food_level: f64,
+    #[serde(
+        alias = "Dimension",
+        default,
+        deserialize_with = "deserialize_dimension"
+    )]
+    pub dimension: Option<String>,
+}
+
+/// Dimensions are saved as numeric ids before 1.16 and as names since
+fn deserialize_dimension<'de, D>(deserializer: D) -> std::result::Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct DimensionVisitor;
+
+    impl<'de> Visitor<'de> for DimensionVisitor {
+        type Value = Option<String>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a dimension id or name")
+        }
+
+        fn visit_i64<E: de::Error>(self, id: i64) -> std::result::Result<Self::Value, E> {
+            let name = match id {
+                -1 => "minecraft:the_nether",
+                1 => "minecraft:the_end",
+                _ => "minecraft:overworld",
+            };
+
+            Ok(Some(String::from(name)))
+        }
+
+        fn visit_u64<E: de::Error>(self, id: u64) -> std::result::Result<Self::Value, E> {
+            self.visit_i64(id as i64)
+        }
+
+        fn visit_str<E: de::Error>(self, name: &str) -> std::result::Result<Self::Value, E> {
+            Ok(Some(String::from(name)))
+        }
+    }
+
+    deserializer.deserialize_any(DimensionVisitor)
 }
 
 #[macro_export]
@@ -101,6 +243,7 @@ macro_rules! mock_nbt {
             score: 102.0,
             health: 10.0,
             food_level: 10.0,
+            dimension: Some(String::from("minecraft:overworld")),
         }
     };
 }